@@ -0,0 +1,115 @@
+use std::path::Path;
+
+use crate::{FileType, HelixLangCfg};
+
+/// Returns the names of the languages which match at least one of the given
+/// paths. The paths are expected to be relative to the project root.
+pub fn languages<P: AsRef<Path>>(languages: &[HelixLangCfg], paths: &[P]) -> Vec<String> {
+    let mut remaining = languages.iter().collect::<Vec<_>>();
+    let mut detected = Vec::new();
+
+    for path in paths {
+        let Some(path) = path.as_ref().to_str() else {
+            continue;
+        };
+        // Windows paths are matched with forward slashes, like in globs.
+        let path = path.replace('\\', "/");
+        let file_name = path.rsplit_once('/').map(|(_, f)| f).unwrap_or(&path);
+
+        remaining.retain(|lang| {
+            let Some(file_types) = lang.file_types.as_ref() else {
+                return true;
+            };
+            let is_match = file_types.iter().any(|ft| match ft {
+                // Helix matches these against the extension as well as the
+                // whole file name, e.g. "Makefile".
+                FileType::Extension(ext) => {
+                    file_name == ext
+                        || file_name
+                            .strip_suffix(ext.as_str())
+                            .is_some_and(|prefix| prefix.ends_with('.'))
+                }
+                FileType::Glob(glob) if glob.contains('/') => {
+                    glob_matches(glob, &path) || glob_matches(&format!("**/{glob}"), &path)
+                }
+                FileType::Glob(glob) => glob_matches(glob, file_name),
+            });
+            if is_match {
+                detected.push(lang.name.clone());
+            }
+            !is_match
+        });
+        if remaining.is_empty() {
+            break;
+        }
+    }
+    detected.sort();
+    detected
+}
+
+/// A small glob matcher supporting `*`, `**` and `?`, which is all the
+/// `file-types` in languages.toml need.
+fn glob_matches(glob: &str, s: &str) -> bool {
+    if let Some(rest) = glob.strip_prefix("**/") {
+        // zero or more complete directories
+        return glob_matches(rest, s)
+            || s.match_indices('/')
+                .any(|(i, _)| glob_matches(rest, &s[i + 1..]));
+    }
+    if let Some(rest) = glob.strip_prefix("**") {
+        return (0..=s.len())
+            .filter(|i| s.is_char_boundary(*i))
+            .any(|i| glob_matches(rest, &s[i..]));
+    }
+    if let Some(rest) = glob.strip_prefix('*') {
+        return (0..=s.len())
+            .filter(|i| s.is_char_boundary(*i))
+            .take_while(|i| !s[..*i].contains('/'))
+            .any(|i| glob_matches(rest, &s[i..]));
+    }
+    let mut glob_chars = glob.chars();
+    let mut chars = s.chars();
+    match (glob_chars.next(), chars.next()) {
+        (None, None) => true,
+        (Some('?'), Some(c)) if c != '/' => glob_matches(glob_chars.as_str(), chars.as_str()),
+        (Some(g), Some(c)) if g == c => glob_matches(glob_chars.as_str(), chars.as_str()),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{DEFAULT_LANGUAGES, parse};
+
+    #[test]
+    fn glob_matches() {
+        use super::glob_matches;
+        assert!(glob_matches("*.rs", "main.rs"));
+        assert!(!glob_matches("*.rs", "src/main.rs"));
+        assert!(glob_matches("**/*.rs", "src/main.rs"));
+        assert!(glob_matches("**/*.rs", "main.rs"));
+        assert!(glob_matches(
+            ".github/workflows/*.yml",
+            ".github/workflows/ci.yml"
+        ));
+        assert!(glob_matches("Makefile.?", "Makefile.a"));
+        assert!(!glob_matches("Makefile.?", "Makefile.ab"));
+    }
+
+    #[test]
+    fn languages() {
+        let languages = parse::languages(DEFAULT_LANGUAGES);
+        let paths = [
+            "Cargo.toml",
+            "src/main.rs",
+            "src/lib.rs",
+            "Makefile",
+            "docs/README.md",
+            ".github/workflows/ci.yml",
+            "unknown.file-type",
+        ];
+        let actual = super::languages(&languages, &paths);
+        let expected = vec!["make", "markdown", "rust", "toml", "yaml"];
+        assert_eq!(actual, expected);
+    }
+}
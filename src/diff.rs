@@ -0,0 +1,326 @@
+//! A small line diff producing unified patches, like `diff --unified`.
+
+use std::{fmt, ops::Range};
+
+/// number of unchanged lines shown around each change
+const CONTEXT: usize = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op {
+    Equal,
+    Delete,
+    Insert,
+}
+
+/// A hunk of a unified patch. The lines keep their prefix (' ', '-' or '+')
+/// and their line break, the last line of a file may lack one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hunk {
+    /// the replaced lines of the old file, zero-based
+    pub old: Range<usize>,
+    /// the lines of the new file, zero-based
+    pub new: Range<usize>,
+    pub lines: Vec<String>,
+}
+
+impl Hunk {
+    /// Parses a hunk the user edited, like `git add -p` does. Lines starting
+    /// with '#' are ignored. The context and removed lines must still match
+    /// the original hunk, otherwise it wouldn't apply.
+    pub fn edit(&self, edited: &str) -> Option<Hunk> {
+        let mut lines: Vec<String> = Vec::new();
+        for line in edited.split_inclusive('\n') {
+            if line.starts_with('\\') {
+                if let Some(prev) = lines.last_mut() {
+                    prev.truncate(prev.trim_end_matches('\n').len());
+                }
+            } else if line.starts_with([' ', '-', '+']) {
+                lines.push(line.into());
+            } else if !line.starts_with(['#', '@']) && line.trim().is_empty() {
+                // editors tend to strip the space of empty context lines
+                lines.push(format!(" {line}"));
+            } else if !line.starts_with(['#', '@']) {
+                return None;
+            }
+        }
+        let old_side = |lines: &[String]| {
+            lines
+                .iter()
+                .filter(|l| !l.starts_with('+'))
+                .map(|l| l[1..].to_string())
+                .collect::<Vec<_>>()
+        };
+        if old_side(&lines) != old_side(&self.lines) {
+            return None;
+        }
+        let new_len = lines.iter().filter(|l| !l.starts_with('-')).count();
+        Some(Hunk {
+            old: self.old.clone(),
+            new: self.new.start..self.new.start + new_len,
+            lines,
+        })
+    }
+}
+
+impl fmt::Display for Hunk {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "@@ -{} +{} @@",
+            range(self.old.start, self.old.len()),
+            range(self.new.start, self.new.len()),
+        )?;
+        for line in &self.lines {
+            f.write_str(line)?;
+            if !line.ends_with('\n') {
+                f.write_str("\n\\ No newline at end of file\n")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Returns a unified patch turning `old` into `new`. The path is relative to
+/// the project root and gets the usual `a/` and `b/` prefixes, so the patch
+/// can be applied with `git apply` or `patch -p1`. Returns an empty string
+/// if there are no differences.
+///
+/// If the old file doesn't exist, `old` is `None` and the patch creates the
+/// file, with the header `git apply` expects for a new file.
+pub fn unified(path: &str, old: Option<&str>, new: &str) -> String {
+    let hunks = hunks(old.unwrap_or_default(), new);
+    if hunks.is_empty() {
+        return String::new();
+    }
+    let mut patch = match old {
+        Some(_) => format!("--- a/{path}\n+++ b/{path}\n"),
+        None => format!(
+            "diff --git a/{path} b/{path}\nnew file mode 100644\n--- /dev/null\n+++ b/{path}\n"
+        ),
+    };
+    for hunk in hunks {
+        patch.push_str(&hunk.to_string());
+    }
+    patch
+}
+
+/// Splits the differences between `old` and `new` into hunks.
+pub fn hunks(old: &str, new: &str) -> Vec<Hunk> {
+    let old_lines = old.split_inclusive('\n').collect::<Vec<_>>();
+    let new_lines = new.split_inclusive('\n').collect::<Vec<_>>();
+    let ops = diff_lines(&old_lines, &new_lines);
+
+    // index into ops, old_lines and new_lines at the start of each op
+    let mut positions = Vec::with_capacity(ops.len() + 1);
+    let (mut o, mut n) = (0, 0);
+    for op in &ops {
+        positions.push((o, n));
+        match op {
+            Op::Equal => (o, n) = (o + 1, n + 1),
+            Op::Delete => o += 1,
+            Op::Insert => n += 1,
+        }
+    }
+    positions.push((o, n));
+
+    let changes = (0..ops.len())
+        .filter(|i| ops[*i] != Op::Equal)
+        .collect::<Vec<_>>();
+    let mut hunks = Vec::new();
+    let mut i = 0;
+    while i < changes.len() {
+        // group changes whose context would overlap into one hunk
+        let start = changes[i].saturating_sub(CONTEXT);
+        let mut end = changes[i] + 1;
+        while i + 1 < changes.len() && changes[i + 1] <= end + 2 * CONTEXT {
+            i += 1;
+            end = changes[i] + 1;
+        }
+        let end = (end + CONTEXT).min(ops.len());
+        i += 1;
+
+        let (old_start, new_start) = positions[start];
+        let (old_end, new_end) = positions[end];
+        let lines = ops[start..end]
+            .iter()
+            .zip(&positions[start..end])
+            .map(|(op, (o, n))| match op {
+                Op::Equal => format!(" {}", old_lines[*o]),
+                Op::Delete => format!("-{}", old_lines[*o]),
+                Op::Insert => format!("+{}", new_lines[*n]),
+            })
+            .collect();
+        hunks.push(Hunk {
+            old: old_start..old_end,
+            new: new_start..new_end,
+            lines,
+        });
+    }
+    hunks
+}
+
+/// Applies some of the hunks computed from `old`, in order. The differences
+/// of the hunks left out are not applied.
+pub fn apply(old: &str, hunks: &[Hunk]) -> String {
+    let old_lines = old.split_inclusive('\n').collect::<Vec<_>>();
+    let mut result = String::new();
+    let mut pos = 0;
+    for hunk in hunks {
+        result.extend(old_lines[pos..hunk.old.start].iter().copied());
+        for line in hunk.lines.iter().filter(|l| !l.starts_with('-')) {
+            result.push_str(&line[1..]);
+        }
+        pos = hunk.old.end;
+    }
+    result.extend(old_lines[pos..].iter().copied());
+    result
+}
+
+/// the line range of a hunk header, one-based
+fn range(start: usize, len: usize) -> String {
+    match len {
+        // an empty range refers to the line before it
+        0 => format!("{start},0"),
+        1 => format!("{}", start + 1),
+        _ => format!("{},{len}", start + 1),
+    }
+}
+
+/// Computes a shortest edit script via the longest common subsequence. The
+/// generated files are small, so the quadratic table is fine.
+fn diff_lines(old: &[&str], new: &[&str]) -> Vec<Op> {
+    let (n, m) = (old.len(), new.len());
+    let mut lcs = vec![vec![0usize; m + 1]; n + 1];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lcs[i][j] = if old[i] == new[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut ops = Vec::with_capacity(n + m);
+    let (mut i, mut j) = (0, 0);
+    while i < n || j < m {
+        if i < n && j < m && old[i] == new[j] {
+            ops.push(Op::Equal);
+            (i, j) = (i + 1, j + 1);
+        } else if j < m && (i == n || lcs[i][j + 1] > lcs[i + 1][j]) {
+            ops.push(Op::Insert);
+            j += 1;
+        } else {
+            ops.push(Op::Delete);
+            i += 1;
+        }
+    }
+    // deletions before insertions within a change, like diff does
+    for k in 1..ops.len() {
+        let mut k = k;
+        while k > 0 && ops[k] == Op::Delete && ops[k - 1] == Op::Insert {
+            ops.swap(k, k - 1);
+            k -= 1;
+        }
+    }
+    ops
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{apply, hunks, unified};
+
+    #[test]
+    fn no_changes() {
+        assert_eq!(unified("f", Some("a\nb\n"), "a\nb\n"), "");
+    }
+
+    #[test]
+    fn two_hunks() {
+        let old = "1\n2\n3\n4\n5\n6\n7\n8\n9\n10\n11\n12\n13\n14\n15\n";
+        let new = "1\n2\nthree\n4\n5\n6\n7\n8\n9\n10\n11\n12\n13\n15\nsixteen\n";
+        let expected = "\
+--- a/.helix/languages.toml
++++ b/.helix/languages.toml
+@@ -1,6 +1,6 @@
+ 1
+ 2
+-3
++three
+ 4
+ 5
+ 6
+@@ -11,5 +11,5 @@
+ 11
+ 12
+ 13
+-14
+ 15
++sixteen
+";
+        assert_eq!(unified(".helix/languages.toml", Some(old), new), expected);
+    }
+
+    #[test]
+    fn new_file_and_missing_newline() {
+        let expected = "\
+diff --git a/f b/f
+new file mode 100644
+--- /dev/null
++++ b/f
+@@ -0,0 +1,2 @@
++a
++b
+";
+        assert_eq!(unified("f", None, "a\nb\n"), expected);
+
+        // an empty file exists already
+        let expected = "\
+--- a/f
++++ b/f
+@@ -0,0 +1,2 @@
++a
++b
+";
+        assert_eq!(unified("f", Some(""), "a\nb\n"), expected);
+
+        let expected = "\
+--- a/f
++++ b/f
+@@ -1,2 +1,2 @@
+ a
+-b
+\\ No newline at end of file
++b
+";
+        assert_eq!(unified("f", Some("a\nb"), "a\nb\n"), expected);
+    }
+
+    #[test]
+    fn apply_some_hunks() {
+        let old = "1\n2\n3\n4\n5\n6\n7\n8\n9\n10\n11\n12\n";
+        let new = "one\n2\n3\n4\n5\n6\n7\n8\n9\n10\n11\ntwelve";
+        let hunks = hunks(old, new);
+        assert_eq!(hunks.len(), 2);
+        assert_eq!(apply(old, &hunks), new);
+        assert_eq!(apply(old, &[]), old);
+        let first = "one\n2\n3\n4\n5\n6\n7\n8\n9\n10\n11\n12\n";
+        assert_eq!(apply(old, &hunks[..1]), first);
+        let last = "1\n2\n3\n4\n5\n6\n7\n8\n9\n10\n11\ntwelve";
+        assert_eq!(apply(old, &hunks[1..]), last);
+    }
+
+    #[test]
+    fn edit_hunk() {
+        let old = "a\nb\nc\n";
+        let hunk = hunks(old, "a\nB\nc\n").remove(0);
+        let edited = hunk
+            .edit("# edit the hunk\n a\n-b\n+bee\n+bee\n c\n")
+            .unwrap();
+        assert_eq!(edited.new, 0..4);
+        assert_eq!(apply(old, &[edited]), "a\nbee\nbee\nc\n");
+
+        // removing the line "-b" means it would be kept, that doesn't apply
+        assert_eq!(hunk.edit(" a\n+B\n c\n"), None);
+    }
+}
@@ -0,0 +1,408 @@
+//! Downloads the languages.toml of a Helix version and caches it. Content is
+//! only used and cached if it parses, so a failed or truncated download can't
+//! break later runs.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+/// serves the files of the Helix repository by git revision
+pub const DEFAULT_MIRROR: &str = "https://raw.githubusercontent.com/helix-editor/helix";
+/// ec2hx shouldn't feel slow because of a bad connection
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(1);
+/// how long a cached languages.toml is used without trying to update it
+pub const DEFAULT_MAX_AGE: Duration = Duration::from_secs(60 * 60 * 24 * 7);
+
+/// Where and how to download the languages.toml.
+#[derive(Debug, Clone)]
+pub struct Source {
+    /// base URL, the file is expected at `{mirror}/{git_ref}/languages.toml`
+    pub mirror: String,
+    pub timeout: Duration,
+    /// never touch the network
+    pub offline: bool,
+}
+
+impl Default for Source {
+    fn default() -> Self {
+        Self {
+            mirror: DEFAULT_MIRROR.into(),
+            timeout: DEFAULT_TIMEOUT,
+            offline: false,
+        }
+    }
+}
+
+impl Source {
+    /// The URL of the languages.toml of a git revision, a commit hash or a
+    /// release tag.
+    pub fn url(&self, git_ref: &str) -> String {
+        let mirror = self.mirror.trim_end_matches('/');
+        format!("{mirror}/{git_ref}/languages.toml")
+    }
+
+    /// Downloads a languages.toml, failing on HTTP errors and invalid content.
+    pub fn fetch(&self, git_ref: &str) -> Result<String, String> {
+        if self.offline {
+            return Err("ec2hx is in offline mode".into());
+        }
+        let content = reqwest::blocking::Client::builder()
+            .timeout(self.timeout)
+            .build()
+            .map_err(|e| e.to_string())?
+            .get(self.url(git_ref))
+            .send()
+            .and_then(|response| response.error_for_status())
+            .and_then(|response| response.text())
+            .map_err(|e| e.to_string())?;
+        crate::parse::try_languages(&content)?;
+        Ok(content)
+    }
+}
+
+/// A directory with one languages.toml per git revision.
+#[derive(Debug, Clone)]
+pub struct Cache {
+    pub dir: PathBuf,
+    /// entries older than this are updated if possible
+    pub max_age: Duration,
+    /// never modify the cache, e.g. with --dry-run
+    pub read_only: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CacheContent {
+    Fresh(String),
+    Stale(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CacheEntry {
+    pub git_ref: String,
+    /// time since the entry was written, if known
+    pub age: Option<Duration>,
+    pub state: EntryState,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntryState {
+    Fresh,
+    /// still used if downloading a newer version fails
+    Stale,
+    /// will be quarantined when it's read the next time
+    Invalid,
+    Quarantined,
+}
+
+impl Cache {
+    pub fn path(&self, git_ref: &str) -> PathBuf {
+        self.dir.join(git_ref).join("languages.toml")
+    }
+
+    /// Reads a cached languages.toml. An invalid entry is moved aside to
+    /// `languages.toml.invalid`, such that it's not used again but can still
+    /// be inspected. A read-only cache leaves it where it is.
+    pub fn read(&self, git_ref: &str) -> Option<CacheContent> {
+        let path = self.path(git_ref);
+        let content = fs::read_to_string(&path).ok()?;
+        if crate::parse::try_languages(&content).is_err() {
+            if !self.read_only {
+                let _ = fs::rename(&path, quarantine_path(&path));
+            }
+            return None;
+        }
+        let mtime = fs::metadata(&path).ok()?.modified().ok()?;
+        if mtime.elapsed().ok()? < self.max_age {
+            Some(CacheContent::Fresh(content))
+        } else {
+            Some(CacheContent::Stale(content))
+        }
+    }
+
+    pub fn write(&self, git_ref: &str, content: &str) -> std::io::Result<()> {
+        let path = self.path(git_ref);
+        fs::create_dir_all(path.parent().unwrap())?;
+        fs::write(path, content)
+    }
+
+    /// Lists the cached entries without modifying them, sorted by revision.
+    pub fn entries(&self) -> Vec<CacheEntry> {
+        let Ok(dirs) = fs::read_dir(&self.dir) else {
+            return Vec::new();
+        };
+        let mut entries = dirs
+            .filter_map(|dir| {
+                let git_ref = dir.ok()?.file_name().to_string_lossy().into_owned();
+                let path = self.path(&git_ref);
+                let (path, state) = match fs::read_to_string(&path) {
+                    Ok(content) if crate::parse::try_languages(&content).is_err() => {
+                        (path, EntryState::Invalid)
+                    }
+                    Ok(_) => (path, EntryState::Fresh),
+                    Err(_) => (quarantine_path(&path), EntryState::Quarantined),
+                };
+                let age = fs::metadata(&path)
+                    .ok()?
+                    .modified()
+                    .ok()
+                    .and_then(|mtime| mtime.elapsed().ok());
+                let state = match (state, age) {
+                    (EntryState::Fresh, Some(age)) if age >= self.max_age => EntryState::Stale,
+                    _ => state,
+                };
+                Some(CacheEntry {
+                    git_ref,
+                    age,
+                    state,
+                })
+            })
+            .collect::<Vec<_>>();
+        entries.sort_by(|a, b| a.git_ref.cmp(&b.git_ref));
+        entries
+    }
+
+    /// Removes all cached entries.
+    pub fn clear(&self) -> std::io::Result<()> {
+        match fs::remove_dir_all(&self.dir) {
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            result => result,
+        }
+    }
+}
+
+fn quarantine_path(path: &Path) -> PathBuf {
+    let mut path = path.as_os_str().to_owned();
+    path.push(".invalid");
+    path.into()
+}
+
+/// Returns the languages.toml of a git revision from the cache if it's fresh.
+/// Otherwise, it's downloaded and cached, falling back to a stale cache entry.
+/// A read-only cache is not updated.
+pub fn fetch_and_cache(cache: &Cache, source: &Source, git_ref: &str) -> Option<String> {
+    let stale_cache = match cache.read(git_ref) {
+        Some(CacheContent::Fresh(content)) => return Some(content),
+        Some(CacheContent::Stale(content)) => Some(content),
+        None => None,
+    };
+
+    let Ok(fetched_languages) = source.fetch(git_ref) else {
+        return stale_cache;
+    };
+
+    if !cache.read_only {
+        // try to update cache, ignore failure
+        let _ = cache.write(git_ref, &fetched_languages);
+    }
+
+    Some(fetched_languages)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs,
+        io::{Read, Write},
+        net::TcpListener,
+        path::PathBuf,
+        time::{Duration, SystemTime},
+    };
+
+    use super::{Cache, CacheContent, DEFAULT_MAX_AGE, EntryState, Source, fetch_and_cache};
+
+    const LANGUAGES: &str = r#"[[language]]
+name = "rust"
+file-types = ["rs"]
+indent = { tab-width = 4, unit = "    " }
+"#;
+
+    /// A stand-in for the HTTP server, answering a single request.
+    fn serve(status: &str, body: &str) -> Source {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let response = format!(
+            "HTTP/1.1 {status}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+            body.len()
+        );
+        std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = Vec::new();
+            let mut buf = [0; 1024];
+            while !request.ends_with(b"\r\n\r\n") {
+                let n = stream.read(&mut buf).unwrap();
+                if n == 0 {
+                    return;
+                }
+                request.extend_from_slice(&buf[..n]);
+            }
+            stream.write_all(response.as_bytes()).unwrap();
+        });
+        Source {
+            mirror: format!("http://{addr}/"),
+            ..Default::default()
+        }
+    }
+
+    fn cache(name: &str) -> Cache {
+        let dir: PathBuf =
+            std::env::temp_dir().join(format!("ec2hx-test-{}-{name}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        Cache {
+            dir,
+            max_age: DEFAULT_MAX_AGE,
+            read_only: false,
+        }
+    }
+
+    #[test]
+    fn valid_download_is_cached() {
+        let cache = cache("valid");
+        let source = serve("200 OK", LANGUAGES);
+        assert_eq!(
+            fetch_and_cache(&cache, &source, "abc").as_deref(),
+            Some(LANGUAGES)
+        );
+        assert_eq!(
+            cache.read("abc"),
+            Some(CacheContent::Fresh(LANGUAGES.into()))
+        );
+        fs::remove_dir_all(cache.dir).unwrap();
+    }
+
+    #[test]
+    fn invalid_downloads_are_rejected() {
+        assert!(serve("404 Not Found", LANGUAGES).fetch("abc").is_err());
+        assert!(serve("200 OK", "404: Not Found").fetch("abc").is_err());
+        // truncated in the middle of a table
+        assert!(serve("200 OK", &LANGUAGES[..40]).fetch("abc").is_err());
+        // truncated after the name of a language
+        assert!(serve("200 OK", &LANGUAGES[..27]).fetch("abc").is_err());
+
+        let cache = cache("invalid");
+        let source = serve("200 OK", "<html>rate limited</html>");
+        assert_eq!(fetch_and_cache(&cache, &source, "abc"), None);
+        assert!(!cache.dir.exists());
+    }
+
+    #[test]
+    fn stale_cache_is_fallback() {
+        let cache = cache("stale");
+        cache.write("abc", LANGUAGES).unwrap();
+        let old = SystemTime::now() - DEFAULT_MAX_AGE - Duration::from_secs(60);
+        fs::File::options()
+            .write(true)
+            .open(cache.path("abc"))
+            .unwrap()
+            .set_modified(old)
+            .unwrap();
+
+        let source = serve("500 Internal Server Error", "");
+        assert_eq!(
+            fetch_and_cache(&cache, &source, "abc").as_deref(),
+            Some(LANGUAGES)
+        );
+
+        // offline, the stale entry is used without trying to update it
+        let source = Source {
+            offline: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            fetch_and_cache(&cache, &source, "abc").as_deref(),
+            Some(LANGUAGES)
+        );
+        assert_eq!(fetch_and_cache(&cache, &source, "def"), None);
+        fs::remove_dir_all(cache.dir).unwrap();
+    }
+
+    #[test]
+    fn invalid_cache_is_quarantined() {
+        let cache = cache("quarantine");
+        cache.write("abc", "404: Not Found").unwrap();
+
+        let source = serve("404 Not Found", "");
+        assert_eq!(fetch_and_cache(&cache, &source, "abc"), None);
+        assert!(!cache.path("abc").exists());
+        let quarantined = cache.dir.join("abc").join("languages.toml.invalid");
+        assert_eq!(fs::read_to_string(quarantined).unwrap(), "404: Not Found");
+        fs::remove_dir_all(cache.dir).unwrap();
+    }
+
+    #[test]
+    fn read_only() {
+        let cache = Cache {
+            read_only: true,
+            ..cache("read-only")
+        };
+        let source = serve("200 OK", LANGUAGES);
+        assert_eq!(
+            fetch_and_cache(&cache, &source, "abc").as_deref(),
+            Some(LANGUAGES)
+        );
+        assert!(!cache.dir.exists());
+
+        fs::create_dir_all(cache.dir.join("abc")).unwrap();
+        fs::write(cache.path("abc"), "404: Not Found").unwrap();
+        assert_eq!(cache.read("abc"), None);
+        assert!(cache.path("abc").exists());
+        fs::remove_dir_all(cache.dir).unwrap();
+    }
+
+    #[test]
+    fn entries() {
+        let cache = cache("entries");
+        assert!(cache.entries().is_empty());
+
+        cache.write("fresh", LANGUAGES).unwrap();
+        cache.write("invalid", "404: Not Found").unwrap();
+        cache.write("quarantined", "404: Not Found").unwrap();
+        cache.read("quarantined");
+        cache.write("stale", LANGUAGES).unwrap();
+        let old = SystemTime::now() - DEFAULT_MAX_AGE - Duration::from_secs(60);
+        fs::File::options()
+            .write(true)
+            .open(cache.path("stale"))
+            .unwrap()
+            .set_modified(old)
+            .unwrap();
+
+        let states = cache
+            .entries()
+            .into_iter()
+            .map(|e| (e.git_ref, e.state))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            states,
+            [
+                ("fresh".into(), EntryState::Fresh),
+                ("invalid".into(), EntryState::Invalid),
+                ("quarantined".into(), EntryState::Quarantined),
+                ("stale".into(), EntryState::Stale),
+            ]
+        );
+        // listing doesn't quarantine anything
+        assert!(cache.path("invalid").exists());
+
+        cache.clear().unwrap();
+        assert!(cache.entries().is_empty());
+        cache.clear().unwrap();
+    }
+
+    #[test]
+    fn mirror() {
+        let source = Source {
+            mirror: "https://mirror.example.com/helix/".into(),
+            ..Default::default()
+        };
+        assert_eq!(
+            source.url("25.01.1"),
+            "https://mirror.example.com/helix/25.01.1/languages.toml"
+        );
+        assert_eq!(
+            Source::default().url("7275b7f8"),
+            "https://raw.githubusercontent.com/helix-editor/helix/7275b7f8/languages.toml"
+        );
+    }
+}
@@ -1,19 +1,45 @@
 use std::{collections::BTreeMap, fmt::Write, str::FromStr};
 
+pub mod detect;
+pub mod diff;
+pub mod fetch;
 pub mod fmt;
+pub mod manifest;
+pub mod merge;
 pub mod parse;
+pub mod stamp;
+pub mod version;
 
 pub static DEFAULT_LANGUAGES: &str = include_str!("../languages.toml");
 
 #[derive(Debug, Clone)]
 pub struct HelixLangCfg {
     name: String,
-    indent: Option<(usize, IndentStyle)>,
+    indent: Option<Indent>,
     file_types: Option<Vec<FileType>>,
     has_formatter: bool,
     raw_toml: toml_edit::Table,
 }
 
+/// The indentation of a language as configured in Helix. The width of the
+/// indent unit and the width of a tab character are independent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Indent {
+    style: IndentStyle,
+    /// same as `tab_width` if the unit is a tab
+    unit_width: usize,
+    tab_width: usize,
+}
+
+impl Indent {
+    fn unit(&self) -> String {
+        match self.style {
+            Space => " ".repeat(self.unit_width),
+            Tab => "\t".into(),
+        }
+    }
+}
+
 impl HelixLangCfg {
     pub fn name(&self) -> &str {
         &self.name
@@ -61,15 +87,181 @@ pub fn merge_languages(languages: &mut Vec<HelixLangCfg>, mut user_languages: Ve
     languages.extend(user_languages);
 }
 
-/// The returned tuple has the contents of config.toml and languages.toml.
-pub fn ec2hx(
-    languages: &[HelixLangCfg],
-    input: &str,
-    fallback_globs: Vec<String>,
-    rulers: bool,
-) -> (String, String, BTreeMap<String, String>) {
+/// Options which influence the generated configuration.
+#[derive(Debug, Clone)]
+pub struct Options {
+    /// additional file types to which global configuration is applied
+    pub fallback_globs: Vec<String>,
+    /// rulers matching max_line_length
+    pub rulers: Rulers,
+    /// soft-wrap lines at max_line_length
+    pub soft_wrap: bool,
+    /// skip overrides which match the Helix defaults anyway
+    pub minimal: bool,
+    /// if set, only these languages are configured
+    pub only_languages: Option<Vec<String>>,
+    /// these languages are never configured
+    pub exclude_languages: Vec<String>,
+    /// indent_size for languages without indent config in Helix, if only
+    /// indent_style is specified
+    pub default_indent_size: usize,
+    /// Location of the .editorconfig relative to the workspace root, if it's
+    /// in a subdirectory. Path globs in the .editorconfig are relative to its
+    /// location, so they are prefixed with this.
+    pub path_prefix: Option<String>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            fallback_globs: Vec::new(),
+            rulers: Rulers::default(),
+            soft_wrap: false,
+            minimal: false,
+            only_languages: None,
+            exclude_languages: Vec::new(),
+            default_indent_size: HX_DEFAULT_TAB_WIDTH,
+            path_prefix: None,
+        }
+    }
+}
+
+/// Configuration of the rulers generated wherever max_line_length is set.
+#[derive(Debug, Clone, Default)]
+pub struct Rulers {
+    /// no rulers are generated if this is empty
+    pub columns: Vec<RulerColumn>,
+    /// overrides `columns` for specific languages
+    pub languages: BTreeMap<String, Vec<RulerColumn>>,
+    /// what relative columns are counted from
+    pub position: RulerPosition,
+}
+
+impl Rulers {
+    /// A single ruler on the first column beyond max_line_length.
+    pub fn after_limit() -> Self {
+        Self {
+            columns: vec![RulerColumn::Relative(0)],
+            ..Default::default()
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.columns.is_empty() && self.languages.is_empty()
+    }
+
+    /// The columns for a language, or the global ones if `lang` is `None`.
+    /// Returns `None` if the editor defaults shouldn't be overridden.
+    fn columns_for(&self, lang: Option<&str>, max_line_length: usize) -> Option<Vec<usize>> {
+        let columns = match lang.and_then(|l| self.languages.get(l)) {
+            Some(columns) => columns,
+            None if self.columns.is_empty() => return None,
+            None => &self.columns,
+        };
+        let reference = match self.position {
+            RulerPosition::Limit => max_line_length,
+            RulerPosition::AfterLimit => max_line_length + 1,
+        };
+        let columns = columns
+            .iter()
+            .filter_map(|column| match *column {
+                RulerColumn::Absolute(column) => Some(column),
+                RulerColumn::Relative(offset) => reference.checked_add_signed(offset),
+            })
+            .filter(|column| *column > 0)
+            .collect();
+        Some(columns)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RulerColumn {
+    Absolute(usize),
+    /// offset from max_line_length, see [RulerPosition]
+    Relative(isize),
+}
+
+impl FromStr for RulerColumn {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let res = if s.starts_with(['+', '-']) {
+            s.parse().map(RulerColumn::Relative)
+        } else {
+            s.parse().map(RulerColumn::Absolute)
+        };
+        res.map_err(|_| format!("invalid ruler column: {s:?}"))
+    }
+}
+
+impl std::fmt::Display for RulerColumn {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RulerColumn::Absolute(column) => write!(f, "{column}"),
+            RulerColumn::Relative(offset) => write!(f, "{offset:+}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RulerPosition {
+    /// relative columns are counted from max_line_length
+    Limit,
+    /// relative columns are counted from the first column beyond
+    /// max_line_length
+    #[default]
+    AfterLimit,
+}
+
+impl FromStr for RulerPosition {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "limit" => Ok(RulerPosition::Limit),
+            "after-limit" => Ok(RulerPosition::AfterLimit),
+            _ => Err(format!("expected 'limit' or 'after-limit', got {s:?}")),
+        }
+    }
+}
+
+impl std::fmt::Display for RulerPosition {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RulerPosition::Limit => write!(f, "limit"),
+            RulerPosition::AfterLimit => write!(f, "after-limit"),
+        }
+    }
+}
+
+impl Options {
+    fn is_selected(&self, lang: &str) -> bool {
+        let is_included = match &self.only_languages {
+            Some(only_languages) => only_languages.iter().any(|l| l == lang),
+            None => true,
+        };
+        is_included && !self.exclude_languages.iter().any(|l| l == lang)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Output {
+    pub config_toml: String,
+    pub languages_toml: String,
+    /// synthetic glob languages and the actual languages they belong to
+    pub glob_languages: BTreeMap<String, String>,
+    /// Languages ec2hx defines for file types Helix doesn't know, like
+    /// `ec2hx-unknown-lang-*` and the plain text fallback. They are selected
+    /// by their own name. Only the name and the file types are set.
+    pub extra_languages: Vec<HelixLangCfg>,
+    /// parts of the EditorConfig which couldn't be converted faithfully
+    pub warnings: Vec<String>,
+}
+
+pub fn ec2hx(languages: &[HelixLangCfg], input: &str, options: &Options) -> Output {
     let fallback_globs = {
-        let mut fallback_globs = fallback_globs;
+        let mut fallback_globs = options.fallback_globs.clone();
         if !fallback_globs.contains(&"*.txt".into()) {
             fallback_globs.push("*.txt".into());
         }
@@ -117,6 +309,21 @@ pub fn ec2hx(
         lang_cfg.with_defaults_from(&global_lang_cfg);
 
         'header_lang_loop: for lang in extract_langs_from_header(header) {
+            let root_relative = |glob: String| match &options.path_prefix {
+                Some(prefix) if glob.contains('/') => {
+                    format!("{prefix}/{}", glob.trim_start_matches('/'))
+                }
+                // matches files in every directory below the .editorconfig,
+                // e.g. [**.md] is the same as [*.md]
+                Some(prefix) => {
+                    let glob = match glob.strip_prefix('*') {
+                        Some(rest) if rest.starts_with('*') => rest,
+                        _ => &glob,
+                    };
+                    format!("{prefix}/**/{glob}")
+                }
+                None => glob,
+            };
             let contains_glob_char = |l: &str| ["/", "**", "?"].iter().any(|c| l.contains(c));
 
             // homebrew example: [**.md] - this could just be [*.md] and
@@ -140,6 +347,13 @@ pub fn ec2hx(
                 // unconditionally. We need to generate a synthetic language
                 // definition for every known language.
                 for supported_lang in languages {
+                    if !options.is_selected(&supported_lang.name) {
+                        continue;
+                    }
+                    // e.g. a language of the user without file types
+                    let Some(lang_file_types) = &supported_lang.file_types else {
+                        continue;
+                    };
                     let matched_name = supported_lang.name.to_string();
                     let mut lang_cfg = lang_cfg.clone();
 
@@ -163,20 +377,19 @@ pub fn ec2hx(
                     raw_toml.remove("injection-regex");
                     raw_toml.insert("grammar", matched_name.clone().into());
 
-                    let file_types = supported_lang
-                        .file_types
-                        .as_ref()
-                        .unwrap()
+                    let file_types = lang_file_types
                         .iter()
                         .map(|ft| match ft {
                             FileType::Extension(s) => format!("{dirname}/*.{s}"),
                             FileType::Glob(s) => format!("{dirname}/{s}"),
                         })
+                        .map(root_relative)
                         .map(FileType::Glob)
                         .collect();
 
                     lang_cfg.raw_toml = Some(raw_toml);
                     lang_cfg.file_types = Some(file_types);
+                    lang_cfg.base_lang = Some(matched_name.clone());
                     glob_languages.insert(name.clone(), matched_name);
                     hx_lang_cfg.insert(name, lang_cfg);
                 }
@@ -198,8 +411,11 @@ pub fn ec2hx(
             let basename_is_more_than_extension =
                 basename.rsplit_once('.').is_some_and(|(pre, _)| pre != "*");
 
+            // Below a subdirectory, even a section like [*.rs] only applies
+            // to some of the files of the language.
             let is_path_glob = !lang_is_stupid_extension_glob && contains_glob_char(&lang)
-                || basename_is_more_than_extension;
+                || basename_is_more_than_extension
+                || options.path_prefix.is_some();
 
             let ext = basename
                 .rsplit_once('.')
@@ -210,14 +426,18 @@ pub fn ec2hx(
             for supported_lang in languages {
                 if supported_lang
                     .file_types
-                    .as_ref()
-                    .unwrap()
                     .iter()
+                    .flatten()
                     .any(|ft| match ft {
                         FileType::Extension(s) => s == &ext,
                         FileType::Glob(s) => s == &basename,
                     })
                 {
+                    if !options.is_selected(&supported_lang.name) {
+                        // The language is known, it just shouldn't be
+                        // configured. Don't treat it as an unknown one.
+                        continue 'header_lang_loop;
+                    }
                     let matched_name = supported_lang.name.to_string();
                     let mut lang_cfg = lang_cfg.clone();
 
@@ -242,7 +462,8 @@ pub fn ec2hx(
                         raw_toml.remove("injection-regex");
                         raw_toml.insert("grammar", matched_name.clone().into());
                         lang_cfg.raw_toml = Some(raw_toml);
-                        lang_cfg.file_types = Some(vec![FileType::Glob(lang)]);
+                        lang_cfg.file_types = Some(vec![FileType::Glob(root_relative(lang))]);
+                        lang_cfg.base_lang = Some(matched_name.clone());
                         glob_languages.insert(name.clone(), matched_name);
                         hx_lang_cfg.insert(name, lang_cfg);
                     } else {
@@ -260,7 +481,7 @@ pub fn ec2hx(
             // An example for this situation: Linux Kconfig files
             let name = make_synthetic_lang_name("unknown", &lang);
             let mut lang_cfg = lang_cfg.clone();
-            lang_cfg.file_types = Some(vec![FileType::Glob(lang)]);
+            lang_cfg.file_types = Some(vec![FileType::Glob(root_relative(lang))]);
 
             // use potential previous matching section as default values,
             // see for example ../test_data/python
@@ -278,18 +499,29 @@ pub fn ec2hx(
     let all_langs_are_customized =
         global_lang_cfg.size.is_some() || global_lang_cfg.style.is_some();
 
-    let languages_toml = if all_langs_are_customized
+    // Languages with their own rulers need an override even if only the
+    // global text-width is configured.
+    let ruler_langs_are_customized = matches!(
+        global_lang_cfg.max_line_length.into(),
+        Some(LineLength::Limit(_))
+    ) && !options.rulers.languages.is_empty();
+
+    let global_langs_are_customized = all_langs_are_customized
         || tab_langs_are_customized
         || langs_without_formatters_are_customized
-    {
-        let mut hx_global_lang_cfg = BTreeMap::new();
+        || ruler_langs_are_customized;
+
+    let mut hx_global_lang_cfg = BTreeMap::new();
+    if global_langs_are_customized {
         for lang in languages {
-            if hx_lang_cfg.contains_key(&lang.name) {
+            if hx_lang_cfg.contains_key(&lang.name) || !options.is_selected(&lang.name) {
                 continue;
             }
             if all_langs_are_customized
-                || tab_langs_are_customized && matches!(lang.indent, Some((_, Tab)))
+                || tab_langs_are_customized
+                    && matches!(lang.indent, Some(Indent { style: Tab, .. }))
                 || langs_without_formatters_are_customized && !lang.has_formatter
+                || ruler_langs_are_customized && options.rulers.languages.contains_key(&lang.name)
             {
                 // language is eligible for customization
             } else {
@@ -307,20 +539,92 @@ pub fn ec2hx(
             // their helix config, we still want editorconfig to take precedence
             // over that. So we do need to generate this (mostly redundant)
             // config for languages that are already configured that way.
-            //
-            // global_lang_cfg.max_line_length = None;
-            // if lang_cfg.size == lang.cfg.size && lang_cfg.style == lang.cfg.style {
-            //     // No need to override values that match the default
-            //     lang_cfg.size = None;
-            //     lang_cfg.style = None;
-            // }
+            // Users who prefer a lean config can opt into that with
+            // Options::minimal, see the function `minimize`.
             hx_global_lang_cfg.insert(lang.name.clone(), lang_cfg);
         }
 
         // global fallback plain text language configuration
         global_lang_cfg.file_types = Some(fallback_globs.into_iter().map(FileType::Glob).collect());
         hx_global_lang_cfg.insert("ec2hx-global-fallback-plain-text".into(), global_lang_cfg);
+    }
+
+    // Synthetic glob languages are selected by the language they are derived
+    // from, the ones without a counterpart in Helix by their own name.
+    let mut extra_languages = Vec::new();
+    for cfgs in [&mut hx_lang_cfg, &mut hx_global_lang_cfg] {
+        cfgs.retain(|name, cfg| {
+            if glob_languages.contains_key(name) || languages.iter().any(|l| &l.name == name) {
+                return true;
+            }
+            extra_languages.push(HelixLangCfg {
+                name: name.clone(),
+                indent: None,
+                file_types: cfg.file_types.clone(),
+                has_formatter: false,
+                raw_toml: toml_edit::Table::new(),
+            });
+            options.is_selected(name)
+        });
+    }
+
+    // Languages without indent config in Helix didn't get any defaults for
+    // partial indent configurations yet.
+    for cfg in hx_lang_cfg
+        .values_mut()
+        .chain(hx_global_lang_cfg.values_mut())
+    {
+        cfg.with_fallback_indent(options.default_indent_size);
+    }
+
+    let mut tab_width_conflicts = BTreeMap::<_, Vec<_>>::new();
+    for (name, cfg) in hx_lang_cfg.iter().chain(hx_global_lang_cfg.iter()) {
+        if let Some(conflict) = cfg.tab_width_conflict() {
+            tab_width_conflicts.entry(conflict).or_default().push(name);
+        }
+    }
+    let mut warnings = tab_width_conflicts
+        .into_iter()
+        .map(|((size, tab_width), langs)| {
+            let langs = match langs.as_slice() {
+                [l1, l2, l3, rest @ ..] if !rest.is_empty() => {
+                    format!("{l1}, {l2}, {l3} and {} more", rest.len())
+                }
+                langs => langs
+                    .iter()
+                    .map(|l| l.as_str())
+                    .collect::<Vec<_>>()
+                    .join(", "),
+            };
+            format!(
+                "indent_size = {size} and tab_width = {tab_width} can't be combined \
+                with indent_style = tab in Helix, using tab-width = {size} for {langs}"
+            )
+        })
+        .collect::<Vec<_>>();
+
+    let is_known = |name: &str| {
+        languages.iter().any(|l| l.name == name) || extra_languages.iter().any(|l| l.name == name)
+    };
+    let selected = options.only_languages.iter().flatten();
+    for name in selected.chain(&options.exclude_languages) {
+        if !is_known(name) {
+            warnings.push(format!("The language {name:?} is unknown, it's ignored."));
+        }
+    }
+
+    if options.minimal {
+        minimize(
+            languages,
+            &mut hx_editor_cfg,
+            &mut hx_lang_cfg,
+            &mut hx_global_lang_cfg,
+            &mut glob_languages,
+            options,
+        );
+    }
 
+    let languages_toml = if global_langs_are_customized {
         ["\
 # language-specific settings:
 
@@ -330,7 +634,7 @@ pub fn ec2hx(
         .chain(
             hx_lang_cfg
                 .into_iter()
-                .map(|(name, cfg)| cfg.to_languages_toml(&name, rulers)),
+                .map(|(name, cfg)| cfg.to_languages_toml(&name, options)),
         )
         .chain(["\
 ################################################################################
@@ -342,21 +646,178 @@ pub fn ec2hx(
         .chain(
             hx_global_lang_cfg
                 .into_iter()
-                .map(|(name, cfg)| cfg.to_languages_toml(&name, rulers)),
+                .map(|(name, cfg)| cfg.to_languages_toml(&name, options)),
         )
         .collect()
     } else {
         hx_lang_cfg
             .into_iter()
-            .map(|(name, cfg)| cfg.to_languages_toml(&name, rulers))
+            .map(|(name, cfg)| cfg.to_languages_toml(&name, options))
             .collect()
     };
 
-    (
-        hx_editor_cfg.to_config_toml(rulers),
+    Output {
+        config_toml: hx_editor_cfg.to_config_toml(options),
         languages_toml,
         glob_languages,
-    )
+        extra_languages,
+        warnings,
+    }
+}
+
+/// Helix' default for `editor.text-width`.
+const HX_DEFAULT_TEXT_WIDTH: usize = 80;
+
+/// Helix' default tab width for languages without indent config.
+const HX_DEFAULT_TAB_WIDTH: usize = 4;
+
+/// The configuration Helix ends up applying to files of a language, taking
+/// into account the fallbacks to the language definition and the global
+/// editor config. Used to detect redundant overrides.
+#[derive(Debug, Clone, PartialEq)]
+struct Effective {
+    indent: Option<(String, usize)>,
+    text_width: usize,
+    rulers: Vec<usize>,
+    soft_wrap: bool,
+    formatter: bool,
+}
+
+impl Effective {
+    fn of(
+        cfg: Option<&LangCfg>,
+        hx: Option<&HelixLangCfg>,
+        editor: &HxEditorCfg,
+        options: &Options,
+    ) -> Self {
+        let hx_toml = hx.map(|hx| &hx.raw_toml);
+        let hx_indent = hx
+            .and_then(|hx| hx.indent)
+            .map(|indent| (indent.unit(), indent.tab_width));
+        let hx_text_width = hx_toml
+            .and_then(|t| t.get("text-width"))
+            .and_then(|v| v.as_integer())
+            .map(|i| i as usize);
+        let hx_rulers = hx_toml
+            .and_then(|t| t.get("rulers"))
+            .and_then(|v| v.as_array())
+            .map(|a| a.iter().filter_map(|v| v.as_integer()).map(|i| i as usize))
+            .map(|rulers| rulers.collect());
+        let editor_limit = editor.max_line_length.into().and_then(LineLength::limit);
+        let editor_rulers = editor_limit
+            .and_then(|max_line_length| options.rulers.columns_for(None, max_line_length))
+            .unwrap_or_default();
+        let default_text_width = hx_text_width
+            .or(editor_limit)
+            .unwrap_or(HX_DEFAULT_TEXT_WIDTH);
+        let default_rulers = hx_rulers.unwrap_or(editor_rulers);
+        let default_soft_wrap = options.soft_wrap && editor_limit.is_some();
+
+        let Some(cfg) = cfg else {
+            return Self {
+                indent: hx_indent,
+                text_width: default_text_width,
+                rulers: default_rulers,
+                soft_wrap: default_soft_wrap,
+                formatter: false,
+            };
+        };
+        let (text_width, rulers, soft_wrap) = match cfg.max_line_length.into() {
+            Some(LineLength::Limit(max_line_length)) => {
+                let lang = hx.map(|hx| hx.name.as_str());
+                let rulers = options.rulers.columns_for(lang, max_line_length);
+                (
+                    max_line_length,
+                    rulers.unwrap_or(default_rulers),
+                    options.soft_wrap || default_soft_wrap,
+                )
+            }
+            Some(LineLength::Off) => (HX_DEFAULT_TEXT_WIDTH, Vec::new(), false),
+            None => (default_text_width, default_rulers, default_soft_wrap),
+        };
+        Self {
+            indent: cfg.indent().or(hx_indent),
+            text_width,
+            rulers,
+            soft_wrap,
+            formatter: cfg.trim_trailing_whitespace.into() == Some(true),
+        }
+    }
+}
+
+/// Removes overrides which don't change the effective configuration compared
+/// to the Helix defaults. Synthetic glob languages which end up configured
+/// exactly like the language they are derived from are removed entirely.
+fn minimize(
+    languages: &[HelixLangCfg],
+    hx_editor_cfg: &mut HxEditorCfg,
+    hx_lang_cfg: &mut BTreeMap<String, LangCfg>,
+    hx_global_lang_cfg: &mut BTreeMap<String, LangCfg>,
+    glob_languages: &mut BTreeMap<String, String>,
+    options: &Options,
+) {
+    let editor_cfg = hx_editor_cfg.clone();
+    let find_hx_lang = |name: &str| languages.iter().find(|l| l.name == name);
+
+    // Glob languages need to be compared against the configuration of the
+    // language they are derived from, so this needs to happen before the
+    // regular languages are minimized.
+    glob_languages.retain(|synthetic, actual| {
+        let Some(cfg) = hx_lang_cfg.get(synthetic) else {
+            return true;
+        };
+        let hx = find_hx_lang(actual);
+        let base_cfg = hx_lang_cfg
+            .get(actual.as_str())
+            .or_else(|| hx_global_lang_cfg.get(actual.as_str()));
+        let effective = Effective::of(Some(cfg), hx, &editor_cfg, options);
+        if effective != Effective::of(base_cfg, hx, &editor_cfg, options) {
+            return true;
+        }
+        hx_lang_cfg.remove(synthetic);
+        false
+    });
+
+    for cfgs in [&mut *hx_lang_cfg, &mut *hx_global_lang_cfg] {
+        cfgs.retain(|name, cfg| {
+            // Synthetic glob languages are copies of the language definition
+            // they are derived from, so they have the same defaults.
+            let hx = find_hx_lang(glob_languages.get(name).unwrap_or(name));
+            let default = Effective::of(None, hx, &editor_cfg, options);
+            let effective = Effective::of(Some(cfg), hx, &editor_cfg, options);
+            if effective.indent == default.indent {
+                cfg.size = Src::default();
+                cfg.style = Src::default();
+                cfg.tab_width = Src::default();
+            }
+            // `off` resets the user config, which isn't known here
+            let is_reset = cfg.max_line_length.into() == Some(LineLength::Off);
+            if effective.text_width == default.text_width
+                && effective.rulers == default.rulers
+                && effective.soft_wrap == default.soft_wrap
+                && !is_reset
+            {
+                cfg.max_line_length = Src::default();
+            }
+            !cfg.is_empty()
+        });
+    }
+    // Synthetic glob languages without any overrides left are useless.
+    glob_languages.retain(|synthetic, _| hx_lang_cfg.contains_key(synthetic));
+
+    // Unset properties and `max_line_length = off` are kept, even though they
+    // reset to the Helix defaults. They are meant to override the user config.
+    if hx_editor_cfg.insert_final_newline.ec == Some(Property::Value(true)) {
+        // Helix inserts a final newline by default
+        hx_editor_cfg.insert_final_newline = Src::default();
+    }
+    if hx_editor_cfg.max_line_length.ec
+        == Some(Property::Value(LineLength::Limit(HX_DEFAULT_TEXT_WIDTH)))
+        && options.rulers.is_empty()
+        && !options.soft_wrap
+    {
+        hx_editor_cfg.max_line_length = Src::default();
+    }
 }
 
 fn make_synthetic_lang_name(kind: &str, lang: &str) -> String {
@@ -524,26 +985,30 @@ impl EditorConfigStrExtension for str {
     }
 }
 
+/// Helix' default for `editor.default-line-ending`.
+const HX_DEFAULT_LINE_ENDING: &str = "native";
+
 #[derive(Debug, Clone, Default)]
 pub struct HxEditorCfg {
-    default_line_ending: Option<&'static str>,
-    insert_final_newline: Option<bool>,
-    max_line_length: Option<usize>,
+    default_line_ending: Src<LineEnding>,
+    insert_final_newline: Src<bool>,
+    max_line_length: Src<LineLength>,
 }
 
 impl HxEditorCfg {
     fn from(section: &BTreeMap<Key, &str>) -> Self {
-        let default_line_ending = section.get(&Key::EndOfLine).and_then(|s| match *s {
-            "lf" => Some("lf"),
-            "crlf" => Some("crlf"),
-            _ => None,
-        });
+        let default_line_ending = section
+            .get(&Key::EndOfLine)
+            .map(|s| Src::parse_ec_prop(s))
+            .unwrap_or_default();
         let insert_final_newline = section
             .get(&Key::InsertFinalNewline)
-            .and_then(|s| s.parse().ok());
+            .map(|s| Src::parse_ec_prop(s))
+            .unwrap_or_default();
         let max_line_length = section
             .get(&Key::MaxLineLength)
-            .and_then(|s| s.parse().ok());
+            .map(|s| Src::parse_ec_prop(s))
+            .unwrap_or_default();
         Self {
             default_line_ending,
             insert_final_newline,
@@ -551,24 +1016,81 @@ impl HxEditorCfg {
         }
     }
 
-    fn to_config_toml(&self, rulers: bool) -> String {
+    /// Properties which are "unset" are reset to the Helix defaults, in case
+    /// the user config sets them.
+    fn to_config_toml(&self, options: &Options) -> String {
         let mut f = String::new();
-        if let Some(default_line_ending) = self.default_line_ending {
-            writeln!(f, "editor.default-line-ending = {default_line_ending:?}").unwrap();
+        match self.default_line_ending.ec {
+            Some(Property::Value(default_line_ending)) => {
+                let default_line_ending = default_line_ending.as_str();
+                writeln!(f, "editor.default-line-ending = {default_line_ending:?}").unwrap();
+            }
+            Some(Property::Unset) => {
+                writeln!(f, "editor.default-line-ending = {HX_DEFAULT_LINE_ENDING:?}").unwrap();
+            }
+            None => {}
         }
-        if let Some(insert_final_newline) = self.insert_final_newline {
-            writeln!(f, "editor.insert-final-newline = {insert_final_newline}").unwrap();
+        match self.insert_final_newline.ec {
+            Some(Property::Value(insert_final_newline)) => {
+                writeln!(f, "editor.insert-final-newline = {insert_final_newline}").unwrap();
+            }
+            Some(Property::Unset) => {
+                writeln!(f, "editor.insert-final-newline = true").unwrap();
+            }
+            None => {}
         }
-        if let Some(max_line_length) = self.max_line_length {
-            writeln!(f, "editor.text-width = {max_line_length}").unwrap();
-            if rulers {
-                writeln!(f, "editor.rulers = [{}]", max_line_length + 1).unwrap();
+        match self.max_line_length.ec {
+            Some(Property::Value(LineLength::Limit(max_line_length))) => {
+                writeln!(f, "editor.text-width = {max_line_length}").unwrap();
+                if let Some(columns) = options.rulers.columns_for(None, max_line_length) {
+                    writeln!(f, "editor.rulers = {columns:?}").unwrap();
+                }
+                if options.soft_wrap {
+                    writeln!(f, "editor.soft-wrap.enable = true").unwrap();
+                    writeln!(f, "editor.soft-wrap.wrap-at-text-width = true").unwrap();
+                }
             }
+            Some(Property::Value(LineLength::Off) | Property::Unset) => {
+                writeln!(f, "editor.text-width = {HX_DEFAULT_TEXT_WIDTH}").unwrap();
+                writeln!(f, "editor.rulers = []").unwrap();
+                if options.soft_wrap {
+                    writeln!(f, "editor.soft-wrap.enable = false").unwrap();
+                }
+            }
+            None => {}
         }
         f
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum LineEnding {
+    Lf,
+    Crlf,
+}
+
+impl LineEnding {
+    fn as_str(self) -> &'static str {
+        match self {
+            LineEnding::Lf => "lf",
+            LineEnding::Crlf => "crlf",
+        }
+    }
+}
+
+/// "cr" is a valid value in EditorConfig, but Helix doesn't support it.
+impl FromStr for LineEnding {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "lf" => Ok(LineEnding::Lf),
+            "crlf" => Ok(LineEnding::Crlf),
+            _ => Err(()),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 enum IndentStyle {
     Space,
@@ -640,7 +1162,7 @@ pub struct LangCfg {
     size: Src<usize>,
     style: Src<IndentStyle>,
     tab_width: Src<usize>,
-    max_line_length: Src<usize>,
+    max_line_length: Src<LineLength>,
     trim_trailing_whitespace: Src<bool>,
     // not part of editorconfig, used to generate custom configs for languages
     // unsupported by Helix
@@ -648,6 +1170,37 @@ pub struct LangCfg {
     // not part of editorconfig, used to generate custom configs for synthetic
     // languages used to support arbitrary path globs
     raw_toml: Option<toml_edit::Table>,
+    // not part of editorconfig, the Helix language a synthetic language is
+    // derived from
+    base_lang: Option<String>,
+}
+
+/// The value of max_line_length, which may be "off" to cancel a limit
+/// inherited from a previous section.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum LineLength {
+    Limit(usize),
+    Off,
+}
+
+impl LineLength {
+    fn limit(self) -> Option<usize> {
+        match self {
+            LineLength::Limit(limit) => Some(limit),
+            LineLength::Off => None,
+        }
+    }
+}
+
+impl FromStr for LineLength {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "off" => Ok(LineLength::Off),
+            s => s.parse().map(LineLength::Limit).map_err(|_| ()),
+        }
+    }
 }
 
 impl FromStr for IndentStyle {
@@ -688,7 +1241,12 @@ impl LangCfg {
     /// because we only want to fill in the gaps of an indent configuration and
     /// not generate unnecessary overrides that match the Helix config anyway.
     fn with_defaults_from_hx_config(&mut self, other: &HelixLangCfg) -> &mut Self {
-        let Some((other_size, other_style)) = other.indent else {
+        let Some(Indent {
+            style: other_style,
+            unit_width: other_size,
+            tab_width: other_tab_width,
+        }) = other.indent
+        else {
             return self;
         };
         if self.tab_width.is_some() && self.style.into() != Some(Space) {
@@ -703,7 +1261,7 @@ impl LangCfg {
                     Tab => self.style = Src::hx(Tab),
                     Space => {
                         // self.style is none and other style is space.
-                        // tab_width will be overruled.
+                        // tab_width only affects the display of tabs.
                         self.style = Src::hx(Space);
                         if self.size.is_none() {
                             self.size = Src::hx(other_size);
@@ -716,12 +1274,42 @@ impl LangCfg {
             // configurations where only size or style is specified.
             // See for example ../test_data/cockroach where only
             // indent_size if set in the global config.
-            if self.size.is_none() {
-                self.size = Src::hx(other_size)
-            }
             if self.style.is_none() {
                 self.style = Src::hx(other_style)
             }
+            if self.size.is_none() {
+                // The width of a space unit doesn't matter if the language
+                // is indented with tabs instead.
+                self.size = match self.style.into() {
+                    Some(Tab) => Src::hx(other_tab_width),
+                    _ => Src::hx(other_size),
+                };
+                // tab_width defaults to indent_size, which doesn't make
+                // sense if the latter comes from Helix.
+                if self.tab_width.is_none() {
+                    self.tab_width = Src::hx(other_tab_width);
+                }
+            }
+        }
+        self
+    }
+
+    /// This completes partial indent configurations for languages which don't
+    /// have an indent config in Helix, so [Self::with_defaults_from_hx_config]
+    /// couldn't fill in the gaps. Like Helix, tabs are used if the style is
+    /// unknown. The size is taken from tab_width if possible.
+    fn with_fallback_indent(&mut self, default_size: usize) -> &mut Self {
+        if self.style.is_none() && self.size.is_some() {
+            self.style = Src::hx(Tab);
+        }
+        if self.style.into() == Some(Space) && self.size.is_none() {
+            self.size = match self.tab_width.into() {
+                Some(tab_width) => Src::hx(tab_width),
+                None => Src::hx(default_size),
+            };
+        }
+        if self.style.into() == Some(Tab) && self.size.is_none() && self.tab_width.is_none() {
+            self.size = Src::hx(default_size);
         }
         self
     }
@@ -755,26 +1343,56 @@ impl LangCfg {
             trim_trailing_whitespace,
             file_types: None,
             raw_toml: None,
+            base_lang: None,
         }
     }
 
-    fn to_languages_toml(&self, lang: &str, rulers: bool) -> String {
-        let indent = 'indent: {
-            let Some(indent_style) = self.style.into() else {
-                break 'indent None;
-            };
-            match (indent_style, self.size.into(), self.tab_width.into()) {
-                (Space, Some(size), _) => Some((" ".repeat(size), size)), // tab_width doesn't affect space
-                (Tab, Some(size), _) | (Tab, None, Some(size)) => Some(("\t".into(), size)),
-                (Space, None, _) | (Tab, None, None) => None,
+    /// The indent unit and tab width, if the configuration is complete.
+    fn indent(&self) -> Option<(String, usize)> {
+        let indent_style = self.style.into()?;
+        match (indent_style, self.size.into(), self.tab_width.into()) {
+            (Space, Some(size), _) => {
+                // tab_width defaults to indent_size, unless both come from
+                // the Helix config
+                let tab_width = match (self.tab_width.ec, self.size.ec) {
+                    (Some(Property::Value(tab_width)), _) => tab_width,
+                    (_, Some(Property::Value(_))) => size,
+                    _ => self.tab_width.hx.unwrap_or(size),
+                };
+                Some((" ".repeat(size), tab_width))
             }
-        };
-        if indent.is_none()
+            // If they disagree, indent_size has precedence. This is reported
+            // as a warning, see Self::tab_width_conflict.
+            (Tab, Some(size), _) | (Tab, None, Some(size)) => Some(("\t".into(), size)),
+            (Space, None, _) | (Tab, None, None) => None,
+        }
+    }
+
+    /// Helix indents with exactly one tab per level, so indent_size and
+    /// tab_width can't be represented if they disagree.
+    fn tab_width_conflict(&self) -> Option<(usize, usize)> {
+        match (
+            self.style.into()?,
+            self.size.into()?,
+            self.tab_width.into()?,
+        ) {
+            (Tab, size, tab_width) if size != tab_width => Some((size, tab_width)),
+            _ => None,
+        }
+    }
+
+    /// Whether this configuration would generate any overrides at all.
+    fn is_empty(&self) -> bool {
+        self.indent().is_none()
             && self.max_line_length.is_none()
             && self.trim_trailing_whitespace.into() != Some(true)
-        {
+    }
+
+    fn to_languages_toml(&self, lang: &str, options: &Options) -> String {
+        if self.is_empty() {
             return String::new();
         }
+        let indent = self.indent();
 
         let mut t = match self.raw_toml.clone() {
             Some(t) => t,
@@ -812,13 +1430,36 @@ impl LangCfg {
             t.insert("indent", m.into());
         }
 
-        if let Some(max_line_length) = self.max_line_length.into() {
-            t.insert("text-width", (max_line_length as i64).into());
-            if rulers {
-                let len: toml_edit::Value = ((max_line_length + 1) as i64).into();
-                let array: toml_edit::Array = [len].into_iter().collect();
-                t.insert("rulers", array.into());
+        match self.max_line_length.into() {
+            Some(LineLength::Limit(max_line_length)) => {
+                t.insert("text-width", (max_line_length as i64).into());
+                let ruler_lang = self.base_lang.as_deref().unwrap_or(lang);
+                if let Some(columns) = options
+                    .rulers
+                    .columns_for(Some(ruler_lang), max_line_length)
+                {
+                    let array: toml_edit::Array = columns.into_iter().map(|c| c as i64).collect();
+                    t.insert("rulers", array.into());
+                }
+                if options.soft_wrap {
+                    let mut m = toml_edit::InlineTable::new();
+                    m.insert("enable", true.into());
+                    m.insert("wrap-at-text-width", true.into());
+                    t.insert("soft-wrap", m.into());
+                }
             }
+            Some(LineLength::Off) => {
+                // Reset to Helix defaults, overriding the global config as
+                // well as the language config of the user.
+                t.insert("text-width", (HX_DEFAULT_TEXT_WIDTH as i64).into());
+                t.insert("rulers", toml_edit::Array::new().into());
+                if options.soft_wrap {
+                    let mut m = toml_edit::InlineTable::new();
+                    m.insert("enable", false.into());
+                    t.insert("soft-wrap", m.into());
+                }
+            }
+            None => {}
         }
 
         if let Some(true) = self.trim_trailing_whitespace.into() {
@@ -884,8 +1525,15 @@ fn snapshot() {
     let languages = parse::languages(DEFAULT_LANGUAGES);
     insta::glob!("..", "test_data/*", |path| {
         let input = std::fs::read_to_string(path).unwrap();
-        let (config_toml, languages_toml, _) =
-            ec2hx(&languages, &input, vec!["*.foo".into()], false);
+        let options = Options {
+            fallback_globs: vec!["*.foo".into()],
+            ..Default::default()
+        };
+        let Output {
+            config_toml,
+            languages_toml,
+            ..
+        } = ec2hx(&languages, &input, &options);
         insta::assert_snapshot!("conf", config_toml);
         insta::assert_snapshot!("lang", languages_toml);
     });
@@ -894,14 +1542,77 @@ fn snapshot() {
 #[test]
 fn rulers() {
     let languages = parse::languages(DEFAULT_LANGUAGES);
+    let options = Options {
+        rulers: Rulers::after_limit(),
+        ..Default::default()
+    };
     // global rulers
     let input = std::fs::read_to_string("test_data/webpack").unwrap();
-    let (config_toml, _, _) = ec2hx(&languages, &input, vec![], true);
+    let config_toml = ec2hx(&languages, &input, &options).config_toml;
     insta::assert_snapshot!("rulers-conf", config_toml);
     // language rulers
     let input = std::fs::read_to_string("test_data/php").unwrap();
-    let (_, languages_toml, _) = ec2hx(&languages, &input, vec![], true);
+    let languages_toml = ec2hx(&languages, &input, &options).languages_toml;
     insta::assert_snapshot!("rulers-lang", languages_toml);
+
+    let options = Options {
+        rulers: Rulers {
+            columns: vec![RulerColumn::Relative(0), RulerColumn::Absolute(120)],
+            languages: [("markdown".into(), vec![RulerColumn::Relative(-10)])].into(),
+            position: RulerPosition::Limit,
+        },
+        only_languages: Some(vec!["markdown".into(), "rst".into()]),
+        ..Default::default()
+    };
+    // custom global rulers
+    let input = std::fs::read_to_string("test_data/webpack").unwrap();
+    let config_toml = ec2hx(&languages, &input, &options).config_toml;
+    insta::assert_snapshot!(config_toml, @r"
+    editor.insert-final-newline = true
+    editor.text-width = 80
+    editor.rulers = [80, 120]
+    ");
+    // custom language rulers
+    let input = std::fs::read_to_string("test_data/php").unwrap();
+    let languages_toml = ec2hx(&languages, &input, &options).languages_toml;
+    let rulers = languages_toml
+        .split("[[language]]")
+        .filter(|lang| lang.contains("rulers"))
+        .flat_map(|lang| lang.lines())
+        .filter(|l| l.starts_with("name") || l.starts_with("rulers"))
+        .collect::<Vec<_>>()
+        .join("\n");
+    insta::assert_snapshot!(rulers, @r#"
+    name = "markdown"
+    rulers = [70]
+    name = "rst"
+    rulers = [100, 120]
+    "#);
+}
+
+#[test]
+fn soft_wrap() {
+    let languages = parse::languages(DEFAULT_LANGUAGES);
+    let options = Options {
+        soft_wrap: true,
+        ..Default::default()
+    };
+    // global soft-wrap
+    let input = std::fs::read_to_string("test_data/webpack").unwrap();
+    let config_toml = ec2hx(&languages, &input, &options).config_toml;
+    insta::assert_snapshot!("soft-wrap-conf", config_toml);
+    // language soft-wrap
+    let input = std::fs::read_to_string("test_data/php").unwrap();
+    let languages_toml = ec2hx(&languages, &input, &options).languages_toml;
+    insta::assert_snapshot!("soft-wrap-lang", languages_toml);
+    // global off resets soft-wrap like it does for languages
+    let input = "root = true\n\n[*]\nmax_line_length = off\n";
+    let config_toml = ec2hx(&languages, input, &options).config_toml;
+    insta::assert_snapshot!(config_toml, @r"
+    editor.text-width = 80
+    editor.rulers = []
+    editor.soft-wrap.enable = false
+    ");
 }
 
 #[test]
@@ -909,7 +1620,11 @@ fn merge_langs() {
     let mut languages = vec![
         HelixLangCfg {
             name: "unchanged".into(),
-            indent: Some((2, Space)),
+            indent: Some(Indent {
+                style: Space,
+                unit_width: 2,
+                tab_width: 2,
+            }),
             file_types: Some(vec![FileType::Glob("*.unchanged".into())]),
             has_formatter: false,
             raw_toml: toml_edit::Table::new(),
@@ -925,7 +1640,11 @@ fn merge_langs() {
     let user_languages = vec![
         HelixLangCfg {
             name: "partial".into(),
-            indent: Some((4, Space)),
+            indent: Some(Indent {
+                style: Space,
+                unit_width: 4,
+                tab_width: 8,
+            }),
             file_types: Some(vec![
                 FileType::Glob("*.partial".into()),
                 FileType::Glob("*.partial.local".into()),
@@ -935,7 +1654,11 @@ fn merge_langs() {
         },
         HelixLangCfg {
             name: "new".into(),
-            indent: Some((3, Tab)),
+            indent: Some(Indent {
+                style: Tab,
+                unit_width: 3,
+                tab_width: 3,
+            }),
             file_types: Some(vec![FileType::Glob("*.new".into())]),
             has_formatter: false,
             raw_toml: toml_edit::Table::new(),
@@ -944,14 +1667,22 @@ fn merge_langs() {
     let expected = vec![
         HelixLangCfg {
             name: "unchanged".into(),
-            indent: Some((2, Space)),
+            indent: Some(Indent {
+                style: Space,
+                unit_width: 2,
+                tab_width: 2,
+            }),
             file_types: Some(vec![FileType::Glob("*.unchanged".into())]),
             has_formatter: false,
             raw_toml: toml_edit::Table::new(),
         },
         HelixLangCfg {
             name: "partial".into(),
-            indent: Some((4, Space)),
+            indent: Some(Indent {
+                style: Space,
+                unit_width: 4,
+                tab_width: 8,
+            }),
             file_types: Some(vec![
                 FileType::Glob("*.partial".into()),
                 FileType::Glob("*.partial.local".into()),
@@ -961,7 +1692,11 @@ fn merge_langs() {
         },
         HelixLangCfg {
             name: "new".into(),
-            indent: Some((3, Tab)),
+            indent: Some(Indent {
+                style: Tab,
+                unit_width: 3,
+                tab_width: 3,
+            }),
             file_types: Some(vec![FileType::Glob("*.new".into())]),
             has_formatter: false,
             raw_toml: toml_edit::Table::new(),
@@ -972,11 +1707,29 @@ fn merge_langs() {
     assert_eq!(languages, expected);
 }
 
+#[test]
+fn user_langs_without_file_types() {
+    let mut languages = parse::languages(DEFAULT_LANGUAGES);
+    let user_languages = "\
+[[language]]
+name = \"my-lang\"
+scope = \"source.my-lang\"
+";
+    assert!(parse::try_languages(user_languages).is_err());
+    let user_languages = parse::user_languages(user_languages).unwrap();
+    merge_languages(&mut languages, user_languages);
+
+    let input = "[docs/*]\nindent_size = 3\n[*.rs]\nindent_size = 2\n";
+    let glob_languages = ec2hx(&languages, input, &Options::default()).glob_languages;
+    assert!(glob_languages.contains_key("ec2hx-glob-lang-docs-*-rust"));
+    assert!(!glob_languages.values().any(|lang| lang == "my-lang"));
+}
+
 #[test]
 fn glob_langs() {
     let languages = parse::languages(DEFAULT_LANGUAGES);
     let input = std::fs::read_to_string("test_data/linux").unwrap();
-    let (_, _, glob_languages) = ec2hx(&languages, &input, vec![], false);
+    let glob_languages = ec2hx(&languages, &input, &Options::default()).glob_languages;
     insta::assert_snapshot!(format!("{glob_languages:#?}"), @r#"
     {
         "ec2hx-glob-lang-tools-perf-**.py": "python",
@@ -986,3 +1739,141 @@ fn glob_langs() {
     }
     "#);
 }
+
+#[test]
+fn path_prefix() {
+    let languages = parse::languages(DEFAULT_LANGUAGES);
+    let input = "\
+[*.rs]
+indent_size = 2
+[**.toml]
+indent_size = 4
+[docs/*.md]
+indent_size = 3
+[/tools/**.py]
+indent_size = 8
+[Kconfig]
+indent_style = tab
+";
+    let options = Options {
+        path_prefix: Some("frontend".into()),
+        ..Default::default()
+    };
+    let languages_toml = ec2hx(&languages, input, &options).languages_toml;
+    let file_types = languages_toml
+        .lines()
+        .filter(|l| l.starts_with("name") || l.starts_with("file-types"))
+        .collect::<Vec<_>>()
+        .join("\n");
+    insta::assert_snapshot!(file_types, @r#"
+    name = "ec2hx-glob-lang-**.toml"
+    file-types = [{ glob = "frontend/**/*.toml" }]
+    name = "ec2hx-glob-lang-*.rs"
+    file-types = [{ glob = "frontend/**/*.rs" }]
+    name = "ec2hx-glob-lang--tools-**.py"
+    file-types = [{ glob = "frontend/tools/**.py" }]
+    name = "ec2hx-glob-lang-docs-*.md"
+    file-types = [{ glob = "frontend/docs/*.md" }]
+    name = "ec2hx-unknown-lang-Kconfig"
+    file-types = [{ glob = "frontend/**/Kconfig" }]
+    "#);
+}
+
+#[test]
+fn minimal() {
+    let languages = parse::languages(DEFAULT_LANGUAGES);
+    let options = Options {
+        minimal: true,
+        ..Default::default()
+    };
+    insta::glob!(
+        "..",
+        "test_data/{cockroach,glob_inheritance,max_line_length_off,php,python,unset_global}",
+        |path| {
+            let input = std::fs::read_to_string(path).unwrap();
+            let Output {
+                config_toml,
+                languages_toml,
+                glob_languages,
+                ..
+            } = ec2hx(&languages, &input, &options);
+            insta::assert_snapshot!(
+                "minimal",
+                format!("{config_toml}\n{languages_toml}\n{glob_languages:#?}")
+            );
+        }
+    );
+}
+
+#[test]
+fn selected_langs() {
+    let languages = parse::languages(DEFAULT_LANGUAGES);
+    let input = std::fs::read_to_string("test_data/cockroach").unwrap();
+    let options = Options {
+        only_languages: Some(vec!["go".into(), "make".into(), "rust".into()]),
+        exclude_languages: vec!["rust".into()],
+        ..Default::default()
+    };
+    let output = ec2hx(&languages, &input, &options);
+    insta::assert_snapshot!(output.languages_toml, @r#"
+    # language-specific settings:
+
+    [[language]]
+    name = "make"
+    indent = { unit = "\t", tab-width = 2 }
+
+    ################################################################################
+
+    # global settings, applied equally to all remaining languages:
+
+    [[language]]
+    name = "go"
+    indent = { unit = "\t", tab-width = 2 }
+    "#);
+    assert!(output.warnings.is_empty());
+
+    // languages ec2hx defines itself are selected by their name
+    let extra_languages = output
+        .extra_languages
+        .iter()
+        .map(|l| l.name.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        extra_languages,
+        [
+            "ec2hx-unknown-lang-*.opt",
+            "ec2hx-global-fallback-plain-text"
+        ]
+    );
+    let options = Options {
+        only_languages: Some(vec!["ec2hx-unknown-lang-*.opt".into(), "gopher".into()]),
+        exclude_languages: vec!["rusty".into()],
+        ..Default::default()
+    };
+    let output = ec2hx(&languages, &input, &options);
+    insta::assert_snapshot!(output.languages_toml, @r#"
+    # language-specific settings:
+
+    [[language]]
+    name = "ec2hx-unknown-lang-*.opt"
+    scope = "text.plain"
+    file-types = [{ glob = "*.opt" }]
+    indent = { unit = "    ", tab-width = 4 }
+
+    ################################################################################
+
+    # global settings, applied equally to all remaining languages:
+    "#);
+    insta::assert_snapshot!(output.warnings.join("\n"), @r#"
+    The language "gopher" is unknown, it's ignored.
+    The language "rusty" is unknown, it's ignored.
+    "#);
+}
+
+#[test]
+fn tab_width_conflict() {
+    let languages = parse::languages(DEFAULT_LANGUAGES);
+    let input = std::fs::read_to_string("test_data/zsh").unwrap();
+    let output = ec2hx(&languages, &input, &Options::default());
+    insta::assert_snapshot!(output.warnings.join("\n"), @"indent_size = 4 and tab_width = 8 can't be combined with indent_style = tab in Helix, using tab-width = 4 for ec2hx-unknown-lang-*.c], ec2hx-unknown-lang-*.h]");
+}
@@ -1,12 +1,15 @@
 use std::{
+    collections::BTreeMap,
     fs,
-    io::{Read, stdin},
-    path::Path,
+    io::{self, IsTerminal, Read, Write, stdin, stdout},
+    path::{Path, PathBuf},
     process::exit,
+    sync::OnceLock,
     time::Duration,
 };
 
 use clap::Parser;
+use ec2hx::version::HxVersion;
 use etcetera::base_strategy::{BaseStrategy, choose_base_strategy};
 
 #[derive(Debug, clap::Parser)]
@@ -18,17 +21,105 @@ struct CliArgs {
     /// add rulers matching max_line_length
     #[arg(long)]
     rulers: bool,
+    /// columns of the rulers, absolute or relative to max_line_length
+    #[arg(long, value_delimiter = ',', allow_hyphen_values = true, long_help = RULER_COLUMNS_HELP)]
+    ruler_columns: Vec<ec2hx::RulerColumn>,
+    /// what relative ruler columns are counted from [possible values: limit, after-limit]
+    #[arg(long, default_value = "after-limit")]
+    ruler_position: ec2hx::RulerPosition,
+    /// ruler columns for a specific language, e.g. 'markdown=+0,+20'
+    #[arg(long, value_parser = parse_language_rulers, allow_hyphen_values = true)]
+    language_rulers: Vec<(String, Vec<ec2hx::RulerColumn>)>,
+    /// soft-wrap lines at max_line_length
+    #[arg(long)]
+    soft_wrap: bool,
+    /// skip overrides which match the Helix defaults
+    #[arg(long, long_help = MINIMAL_HELP)]
+    minimal: bool,
+    /// only configure these languages
+    #[arg(long, value_delimiter = ',', conflicts_with = "auto")]
+    only_languages: Vec<String>,
+    /// never configure these languages
+    #[arg(long, value_delimiter = ',')]
+    exclude_languages: Vec<String>,
+    /// indent_size for languages without indent config in Helix
+    #[arg(long, default_value_t = 4, long_help = DEFAULT_INDENT_SIZE_HELP)]
+    default_indent_size: usize,
+    /// only configure languages with files in the working tree
+    #[arg(long, long_help = AUTO_HELP)]
+    auto: bool,
+    /// the .editorconfig file to read, '-' for stdin
+    #[arg(long, value_name = "PATH")]
+    editorconfig: Option<PathBuf>,
+    /// the directory to write the configuration to [default: .helix]
+    #[arg(long, value_name = "PATH", global = true, long_help = OUTPUT_DIR_HELP)]
+    output_dir: Option<PathBuf>,
+    /// only use the .editorconfig and the bundled languages.toml
+    #[arg(long, long_help = PORTABLE_HELP)]
+    portable: bool,
+    /// list the files that would be changed without touching them
+    #[arg(long, global = true)]
+    dry_run: bool,
+    /// print the generated configuration instead of writing it
+    #[arg(long, long_help = STDOUT_HELP, conflicts_with_all = ["dry_run", "check"])]
+    stdout: bool,
+    /// fail if the configuration in .helix is out of date
+    #[arg(long, long_help = CHECK_HELP, conflicts_with = "dry_run")]
+    check: bool,
+    /// review conflicting changes hunk by hunk
+    #[arg(long, long_help = INTERACTIVE_HELP, conflicts_with_all = ["dry_run", "stdout", "check"])]
+    interactive: bool,
+    /// use this languages.toml instead of the one of the installed Helix
+    #[arg(long, value_name = "PATH", env = "EC2HX_LANGUAGES_FILE", long_help = LANGUAGES_FILE_HELP)]
+    languages_file: Option<PathBuf>,
+    /// base URL to download the languages.toml of Helix from
+    #[arg(long, value_name = "URL", global = true, env = "EC2HX_MIRROR", default_value = ec2hx::fetch::DEFAULT_MIRROR, long_help = MIRROR_HELP)]
+    mirror: String,
+    /// timeout of downloads in seconds
+    #[arg(long, value_name = "SECONDS", global = true, env = "EC2HX_TIMEOUT", default_value_t = ec2hx::fetch::DEFAULT_TIMEOUT.as_secs())]
+    timeout: u64,
+    /// number of days a downloaded languages.toml is used before updating it
+    #[arg(long, value_name = "DAYS", global = true, env = "EC2HX_CACHE_MAX_AGE", default_value_t = ec2hx::fetch::DEFAULT_MAX_AGE.as_secs() / SECS_PER_DAY)]
+    cache_max_age: u64,
+    /// never access the network
+    #[arg(long, global = true, env = "EC2HX_OFFLINE", value_parser = clap::builder::BoolishValueParser::new(), long_help = OFFLINE_HELP)]
+    offline: bool,
     #[command(subcommand)]
     cmd: Option<Subcommand>,
 }
 
 #[derive(Debug, clap::Subcommand)]
 enum Subcommand {
+    /// remove the files generated by ec2hx
+    #[command(long_about = CLEAN_HELP)]
+    Clean {
+        /// clean up after all projects, not just the current one
+        #[arg(long)]
+        all: bool,
+    },
+    /// manage the cache of Helix language definitions
+    #[command(subcommand, long_about = CACHE_HELP)]
+    Cache(CacheCommand),
     /// used internally to apply trim_trailing_withspace via a formatter
     #[command(hide = true)]
     TrimTrailingWhitespace,
 }
 
+#[derive(Debug, clap::Subcommand)]
+enum CacheCommand {
+    /// list the cached entries with their age and validity
+    List,
+    /// remove all cached entries
+    Clear,
+    /// download the languages.toml of a Helix version into the cache
+    Prefetch {
+        /// a release like 25.01.1 or a commit hash [default: the installed version]
+        version: Option<String>,
+    },
+    /// print the path of the cache directory
+    Path,
+}
+
 const LONG_ABOUT: &str = "\
 ec2hx - convert EditorConfig to Helix configuration
 Simply run ec2hx in a directory with a .editorconfig file and a .helix
@@ -39,8 +130,8 @@ features are supported, but the important ones should work fine
 (indentation, line ending, final newline).
 The .helix directory will ignore itself using a .helix/.gitignore file, so
 don't worry about accidentally committing these files to version control.
-Existing files won't be clobbered, to preserve any manual adjustments you
-have made.
+Existing files are updated in place. Manual adjustments you have made are
+preserved.
 For more information, visit <https://github.com/senekor/ec2hx>";
 
 const FALLBACK_GLOBS_HELP: &str = "\
@@ -59,9 +150,180 @@ included.
 
 Example: --fallback-globs '*.foo,*.bar'";
 
+const RULER_COLUMNS_HELP: &str = "\
+columns of the rulers, absolute or relative to max_line_length
+
+Rulers are only generated where max_line_length is set. Columns with a sign are
+relative to max_line_length, columns without one are absolute. Which column a
+relative offset is counted from is controlled with --ruler-position. This
+option implies --rulers.
+
+Example: --ruler-columns '+0,100'";
+
+const MINIMAL_HELP: &str = "\
+skip overrides which match the Helix defaults
+
+By default, ec2hx generates overrides for every language affected by the
+.editorconfig, even if the Helix defaults already match. That way, the
+EditorConfig still takes precedence if you later change your Helix config.
+With this flag, only the overrides that actually make a difference compared to
+the defaults of Helix are generated. That's useful if you want to commit the
+.helix directory to version control and keep it readable.
+
+Your own languages.toml is taken into account, unless --portable is used, but
+your config.toml is not. Explicit resets like max_line_length = off or unset
+are always kept, because they are meant to override it.";
+
+const DEFAULT_INDENT_SIZE_HELP: &str = "\
+indent_size for languages without indent config in Helix
+
+If a section of the .editorconfig only specifies indent_style, the indent_size
+is taken from the Helix config of the language. Some languages don't have one,
+in which case tab_width is used if possible, or else this default.
+If only indent_size is specified for such a language, it's indented with tabs,
+like Helix does by default.";
+
+const AUTO_HELP: &str = "\
+only configure languages with files in the working tree
+
+A global [*] section in the .editorconfig makes ec2hx generate configuration
+for every single language Helix supports. With this flag, ec2hx scans the
+working tree of the workspace, the directory .helix belongs to, and only
+generates configuration for the languages that are actually used. If the
+workspace is a git repository, ignored files are not considered.
+
+Languages added to the project later won't be configured until you run ec2hx
+again. Use --only-languages instead to select the languages explicitly.";
+
+const STDOUT_HELP: &str = "\
+print the generated configuration instead of writing it
+
+The generated config.toml and languages.toml are printed, each preceded by a
+comment with its name. Nothing is written and no existing files are merged,
+so this shows the configuration exactly as ec2hx generates it. Warnings are
+printed to stderr.";
+
+const OUTPUT_DIR_HELP: &str = "\
+the directory to write the configuration to [default: .helix]
+
+Helix only loads the configuration from a .helix directory in the workspace
+root, so this is mostly useful for scripts and tools that need it elsewhere.
+The manifest records the generated files as belonging to the parent of this
+directory. Pass the same option to 'ec2hx clean' to remove them again.";
+
+const SECS_PER_DAY: u64 = 60 * 60 * 24;
+
+const LANGUAGES_FILE_HELP: &str = "\
+use this languages.toml instead of the one of the installed Helix
+
+By default, ec2hx looks for the languages.toml matching the installed Helix
+version in local runtime directories, the cache and on GitHub. With this
+option, that detection is skipped completely and the given file is used. It
+must be a complete languages.toml like the one in the Helix repository. Your
+own languages.toml in the Helix config directory is still taken into account.
+This option is ignored with --portable.";
+
+const MIRROR_HELP: &str = "\
+base URL to download the languages.toml of Helix from
+
+The languages.toml of a Helix version is expected at
+'<URL>/<commit or release tag>/languages.toml', like on GitHub. Use this to
+point ec2hx to an internal mirror of the Helix repository.";
+
+const OFFLINE_HELP: &str = "\
+never access the network
+
+The languages.toml of Helix is only taken from local runtime directories and
+the cache, even if the cache entry is outdated. If it's not available, the one
+bundled with ec2hx is used. Use 'ec2hx cache prefetch' beforehand to fill the
+cache.";
+
+const PORTABLE_HELP: &str = "\
+only use the .editorconfig and the bundled languages.toml
+
+By default, the generated configuration depends on the machine ec2hx runs on.
+The languages.toml matching the installed Helix version is used, your own
+languages.toml is taken into account and 'hx --health' decides which
+languages have a formatter. With this flag, only the languages.toml bundled
+with ec2hx and the .editorconfig are used, so the output is identical on every
+machine and a team can commit it. The .helix/.gitignore only ignores backups
+and patches then. Commit .helix/.ec2hx as well, ec2hx needs it to tell your
+own changes apart when updating the files.
+
+Path glob sections like [docs/*.md] need query files in the Helix config
+directory, which are not part of .helix. Everyone has to run ec2hx --portable
+once, otherwise these files are not highlighted.";
+
+const CLEAN_HELP: &str = "\
+remove the files generated by ec2hx
+
+ec2hx keeps a manifest of the files and directories it generated for each
+project, including the query files in the Helix config directory. This
+command removes exactly those, for the project in the current directory or,
+with --all, for every project. Files which are also used by another project
+are kept. Directories are only removed if they are empty afterwards.
+
+A config.toml or languages.toml which existed before ec2hx first ran is only
+merged into. It belongs to you and is not removed, nor are its backups.";
+
+const CHECK_HELP: &str = "\
+fail if the configuration in .helix is out of date
+
+The configuration is generated in memory and compared with the files in .helix.
+Differences are printed as a patch and ec2hx exits with a non-zero status.
+Entries you added to the files yourself are not considered a difference, as
+long as the previously generated configuration in .helix/.ec2hx is available.
+Without it, the files must match exactly. Nothing is written, which makes this
+useful in CI if you commit .helix to version control.
+
+CI machines usually don't have Helix installed, so generate the configuration
+with --portable and check it with --portable as well. Otherwise, the result
+depends on the Helix version of the machine.
+
+Example: ec2hx --check --portable";
+
+const CACHE_HELP: &str = "\
+manage the cache of Helix language definitions
+
+If the languages.toml of the installed Helix version can't be found locally,
+ec2hx downloads it from GitHub and caches it for a week. There is one entry
+per Helix version. Use 'prefetch' to fill the cache ahead of time, such that
+later runs work offline.";
+
+const INTERACTIVE_HELP: &str = "\
+review conflicting changes hunk by hunk
+
+If you changed an entry in .helix that ec2hx wants to change as well, your
+version is kept and a patch with the proposed change is generated. With this
+flag, each hunk of that patch is shown instead and you decide whether to
+accept, reject or edit it, like 'git add -p'. The result is written directly.
+This requires a terminal, otherwise patches are generated as usual.";
+
 fn main() {
     let args = CliArgs::parse();
 
+    if let Some(Subcommand::Clean { all }) = args.cmd {
+        clean(&helix_dir(&args), all, args.dry_run);
+        exit(0);
+    }
+
+    let languages_cache = ec2hx::fetch::Cache {
+        dir: languages_cache_dir(),
+        max_age: Duration::from_secs(args.cache_max_age * SECS_PER_DAY),
+        // nothing is written without a plan
+        read_only: args.dry_run || args.stdout || args.check,
+    };
+    let source = ec2hx::fetch::Source {
+        mirror: args.mirror.clone(),
+        timeout: Duration::from_secs(args.timeout),
+        offline: args.offline,
+    };
+
+    if let Some(Subcommand::Cache(cmd)) = args.cmd {
+        cache(cmd, &languages_cache, &source, args.dry_run);
+        exit(0);
+    }
+
     if let Some(Subcommand::TrimTrailingWhitespace) = args.cmd {
         let mut input = String::new();
         if let Err(err) = stdin().read_to_string(&mut input) {
@@ -73,72 +335,187 @@ fn main() {
         exit(0);
     }
 
-    let Ok(editorconfig) = std::fs::read_to_string(".editorconfig") else {
-        println!("ERROR: Failed to read the .editorconfig file.");
-        println!("       Please check your current working directory.");
+    let editorconfig_path = args
+        .editorconfig
+        .clone()
+        .unwrap_or_else(|| ".editorconfig".into());
+    let editorconfig = if editorconfig_path == Path::new("-") {
+        let mut input = String::new();
+        stdin().read_to_string(&mut input).map(|_| input)
+    } else {
+        fs::read_to_string(&editorconfig_path)
+    };
+    let Ok(editorconfig) = editorconfig else {
+        println!("ERROR: Failed to read {}.", editorconfig_path.display());
+        if args.editorconfig.is_none() {
+            println!("       Please check your current working directory.");
+        }
         exit(1);
     };
+    let helix_dir = helix_dir(&args);
+    let path_prefix = path_prefix(&args);
+    let config_path = &helix_dir.join("config.toml").to_string_lossy().into_owned();
+    let languages_path = &helix_dir
+        .join("languages.toml")
+        .to_string_lossy()
+        .into_owned();
 
-    let mut languages = match fetch_and_cache_languages() {
-        Some(l) => ec2hx::parse::languages(&l),
-        None => ec2hx::parse::languages(ec2hx::DEFAULT_LANGUAGES),
+    // The portable output must not depend on the installed Helix version.
+    let detect = !args.portable && args.languages_file.is_none();
+    let hx_version = detect.then(get_hx_version).flatten();
+    let (mut languages, languages_warning) = if args.portable {
+        (ec2hx::parse::languages(ec2hx::DEFAULT_LANGUAGES), None)
+    } else if let Some(path) = &args.languages_file {
+        (read_languages_file(path), None)
+    } else {
+        let (languages, warning) = find_languages(hx_version.as_ref(), &languages_cache, &source);
+        (ec2hx::parse::languages(&languages), warning)
     };
-    if let Some(user_languages) = read_user_languages() {
+    if let Some(user_languages) = read_user_languages().filter(|_| !args.portable) {
         ec2hx::merge_languages(&mut languages, user_languages);
     }
 
-    if editorconfig.contains("trim_trailing_whitespace") {
+    if editorconfig.contains("trim_trailing_whitespace") && !args.portable {
         languages = match set_has_formattes_from_hx_health(&languages) {
             Some(new_languages) => new_languages,
             None => languages,
         };
     }
 
-    let (config_toml, languages_toml, glob_languages) =
-        ec2hx::ec2hx(&languages, &editorconfig, args.fallback_globs, args.rulers);
+    let stamp = ec2hx::stamp::Stamp {
+        version: env!("CARGO_PKG_VERSION").into(),
+        hx: hx_version.map(|v| v.git_ref().into()),
+        input: ec2hx::stamp::hash(&editorconfig),
+        options: stamp_options(&args),
+    };
+
+    let project_files = args
+        .auto
+        .then(|| list_project_files(&workspace_root(&args)));
+
+    let ruler_columns = ruler_columns(&args);
+    let mut options = ec2hx::Options {
+        fallback_globs: args.fallback_globs,
+        rulers: ec2hx::Rulers {
+            columns: ruler_columns,
+            languages: args.language_rulers.into_iter().collect(),
+            position: args.ruler_position,
+        },
+        soft_wrap: args.soft_wrap,
+        minimal: args.minimal,
+        only_languages: (!args.only_languages.is_empty()).then_some(args.only_languages),
+        exclude_languages: args.exclude_languages,
+        default_indent_size: args.default_indent_size,
+        path_prefix,
+    };
+    if let Some(files) = project_files {
+        // The languages ec2hx defines for file types Helix doesn't know are
+        // detected by their file types as well.
+        let extra_languages = ec2hx::ec2hx(&languages, &editorconfig, &options).extra_languages;
+        let mut detected = ec2hx::detect::languages(&languages, &files);
+        detected.extend(ec2hx::detect::languages(&extra_languages, &files));
+        options.only_languages = Some(detected);
+    }
+    let ec2hx::Output {
+        config_toml,
+        languages_toml,
+        glob_languages,
+        warnings,
+        ..
+    } = ec2hx::ec2hx(&languages, &editorconfig, &options);
+    for warning in languages_warning.into_iter().chain(warnings) {
+        if args.stdout || args.check {
+            eprintln!("WARN: {warning}");
+        } else {
+            println!("WARN: {warning}");
+        }
+    }
 
     let prepend_generated_notice = |s| {
         format!(
             "\
 # This file is generated by ec2hx. <https://github.com/senekor/ec2hx>
 #
-# Feel free to edit it, ec2hx won't overwrite it if you run it twice. If you
-# have the program 'diff' installed, it will instead generate a patch that you
-# can inspect and apply at your leisure.
-
+# Feel free to edit it. When you run ec2hx again, it only updates the entries it
+# generated itself and keeps your changes. If your changes conflict with the
+# .editorconfig, it generates a patch that you can inspect and apply at your
+# leisure.
+#
+{stamp}
 {s}"
         )
     };
     let config_toml = prepend_generated_notice(config_toml);
     let languages_toml = prepend_generated_notice(languages_toml);
 
-    fs::create_dir_all(".helix").expect("failed to create .helix directory");
+    if args.stdout {
+        println!("# ==> {config_path} <==");
+        println!("{config_toml}");
+        println!("# ==> {languages_path} <==");
+        print!("{languages_toml}");
+        exit(0);
+    }
 
-    if !fs::exists(".helix/.gitignore").is_ok_and(|b| b) {
-        fs::write(
-            ".helix/.gitignore",
-            "# This file is generated by ec2hx. <https://github.com/senekor/ec2hx>\n*\n",
-        )
-        .expect("failed to write .helix/.gitignore");
+    if args.check {
+        let languages_ok = check_toml(languages_path, &languages_toml);
+        let config_ok = check_toml(config_path, &config_toml);
+        if !(languages_ok && config_ok) {
+            eprintln!(
+                "ERROR: The configuration in {} doesn't match the .editorconfig.",
+                helix_dir.display()
+            );
+            eprintln!("       Run ec2hx without --check to update it.");
+            exit(1);
+        }
+        exit(0);
     }
-    try_write_toml(".helix/languages.toml", languages_toml);
-    try_write_toml(".helix/config.toml", config_toml);
+
+    let interactive = args.interactive && stdin().is_terminal() && stdout().is_terminal();
+    if args.interactive && !interactive {
+        println!("WARN: --interactive requires a terminal, conflicts are written to patches.");
+    }
+
+    let project = project_dir(&helix_dir);
+    let mut manifest = read_manifest();
+    let mut plan = Plan::new(args.dry_run);
+    plan.recorded = manifest
+        .project(&project)
+        .map(|entry| entry.files.iter().cloned().collect())
+        .unwrap_or_default();
+
+    let gitignore = "# This file is generated by ec2hx. <https://github.com/senekor/ec2hx>\n*\n";
+    // Portable output is meant to be committed, including the previously
+    // generated configuration in .ec2hx. Backups and patches are local.
+    let portable_gitignore =
+        "# This file is generated by ec2hx. <https://github.com/senekor/ec2hx>\n*.bak\n*.patch\n";
+    // don't overwrite a .gitignore of the user
+    let prev_gitignore = fs::read_to_string(helix_dir.join(".gitignore")).ok();
+    if prev_gitignore.is_none_or(|prev| prev == gitignore || prev == portable_gitignore) {
+        let contents = if args.portable {
+            portable_gitignore
+        } else {
+            gitignore
+        };
+        plan.write(helix_dir.join(".gitignore"), contents);
+    }
+    plan.dir(&helix_dir);
+    plan.dir(helix_dir.join(".ec2hx"));
+    try_write_toml(&mut plan, languages_path, languages_toml, interactive);
+    try_write_toml(&mut plan, config_path, config_toml, interactive);
 
     if !glob_languages.is_empty() {
+        if args.portable {
+            println!(
+                "WARN: Path glob sections need query files in the Helix config directory, which \
+                 can't be committed. Everyone using this configuration has to run ec2hx \
+                 --portable once, otherwise these files are not highlighted."
+            );
+        }
         let queries_dir = helix_config_dir().join("runtime").join("queries");
         for (synthetic, actual) in glob_languages {
             let lang_dir = queries_dir.join(synthetic);
-            if fs::create_dir_all(&lang_dir).is_err() {
-                continue;
-            }
-            if fs::write(
-                lang_dir.join(".gitignore"),
-                "# This file is generated by ec2hx. <https://github.com/senekor/ec2hx>\n*\n",
-            )
-            .is_err()
-            {
-                continue;
-            }
+            plan.dir(&lang_dir);
+            plan.write(lang_dir.join(".gitignore"), gitignore);
 
             let inherits = format!(
                 "\
@@ -154,93 +531,877 @@ fn main() {
                 "textobjects.scm",
             ];
             for query in queries {
-                let _ = fs::write(lang_dir.join(query), &inherits);
+                plan.write(lang_dir.join(query), &inherits);
             }
         }
     }
 
-    #[cfg(unix)] // try to reload config by sending signal
-    let _ = std::process::Command::new("killall")
-        .args(["-USR1", "hx"])
-        .status();
+    plan.prune_glob_lang_dirs(&manifest, &project);
+
+    // try to reload config by sending signal
+    plan.reload_hx = cfg!(unix) && !plan.changes.is_empty();
+
+    plan.record_in_manifest(&mut manifest, &project);
+
+    if plan.dry_run {
+        plan.print();
+    } else {
+        plan.apply();
+    }
 }
 
-fn fetch_and_cache_languages() -> Option<String> {
-    let hx_version_hash = get_hx_version_hash()?;
+/// Changes to the filesystem. They are collected before anything is touched,
+/// such that they can be listed instead with --dry-run.
+#[derive(Debug)]
+struct Plan {
+    changes: Vec<Change>,
+    /// every file of the project owned by ec2hx, whether it changes or not
+    generated_files: Vec<PathBuf>,
+    /// the files recorded in the manifest for the project by previous runs
+    recorded: Vec<PathBuf>,
+    generated_dirs: Vec<PathBuf>,
+    /// previously generated directories which are not needed anymore
+    stale_dirs: Vec<PathBuf>,
+    reload_hx: bool,
+    /// the changes are only listed, messages must not claim otherwise
+    dry_run: bool,
+    /// used to name the backups
+    timestamp: String,
+}
 
-    let strategy = choose_base_strategy().expect("Unable to find the config directory!");
-    let mut cache_path = strategy.cache_dir();
-    cache_path.push("ec2hx");
-    cache_path.push(&hx_version_hash);
-    cache_path.push("languages.toml");
-
-    let stale_cache = match read_cache(&cache_path) {
-        Some(CacheContent::Fresh(content)) => return Some(content),
-        Some(CacheContent::Stale(content)) => Some(content),
-        None => None,
+#[derive(Debug)]
+enum Change {
+    Write {
+        path: PathBuf,
+        contents: String,
+        /// where to keep the previous contents, if the file exists
+        backup: Option<PathBuf>,
+    },
+    Remove {
+        path: PathBuf,
+        backup: Option<PathBuf>,
+    },
+    /// only removed if it's empty
+    RemoveDir(PathBuf),
+}
+
+impl Plan {
+    fn new(dry_run: bool) -> Self {
+        Self {
+            changes: Vec::new(),
+            generated_files: Vec::new(),
+            recorded: Vec::new(),
+            generated_dirs: Vec::new(),
+            stale_dirs: Vec::new(),
+            reload_hx: false,
+            dry_run,
+            timestamp: timestamp(),
+        }
+    }
+
+    /// Files which already have the same contents are skipped. Existing files
+    /// of the user are merged into, but they stay the user's. They are not
+    /// recorded in the manifest, such that `ec2hx clean` leaves them alone.
+    fn write(&mut self, path: impl Into<PathBuf>, contents: impl Into<String>) {
+        let (path, contents) = (path.into(), contents.into());
+        let is_owned = self.is_owned(&path);
+        if is_owned {
+            self.generated_files.push(path.clone());
+        }
+        if fs::read_to_string(&path).is_ok_and(|prev| prev == contents) {
+            return;
+        }
+        let backup = path.exists().then(|| self.backup_path(&path)).flatten();
+        if is_owned {
+            self.generated_files.extend(backup.clone());
+        }
+        self.changes.push(Change::Write {
+            path,
+            contents,
+            backup,
+        });
+    }
+
+    fn remove(&mut self, path: impl Into<PathBuf>) {
+        let path = path.into();
+        self.generated_files.retain(|p| *p != path);
+        if fs::exists(&path).is_ok_and(|b| b) {
+            let backup = self.backup_path(&path);
+            if self.is_owned(&path) {
+                self.generated_files.extend(backup.clone());
+            }
+            self.changes.push(Change::Remove { path, backup });
+        }
+    }
+
+    /// Whether a file belongs to ec2hx: it doesn't exist yet, was created by
+    /// a previous run or has the notice of ec2hx.
+    fn is_owned(&self, path: &Path) -> bool {
+        !path.exists()
+            || self.recorded.contains(&absolute(path))
+            || fs::read_to_string(path).is_ok_and(|file| ec2hx::stamp::is_generated(&file))
+    }
+
+    /// Records a generated directory. Directories are created as needed when
+    /// writing files, this is only relevant for cleaning them up.
+    fn dir(&mut self, path: impl Into<PathBuf>) {
+        self.generated_dirs.push(path.into());
+    }
+
+    /// e.g. "languages.toml.20250224-153000.bak". The oldest backups are
+    /// removed, such that there are at most [MAX_BACKUPS] per file. Internal
+    /// state like the previously generated configuration and patches is not
+    /// backed up at all, it's regenerated anyway.
+    fn backup_path(&mut self, path: &Path) -> Option<PathBuf> {
+        // a .gitignore is only overwritten if it was generated
+        let is_internal = path.extension().is_some_and(|ext| ext == "patch")
+            || path.file_name().is_some_and(|name| name == ".gitignore")
+            || path
+                .parent()
+                .and_then(Path::file_name)
+                .is_some_and(|dir| dir == ".ec2hx");
+        if is_internal {
+            return None;
+        }
+        let backups = backups(path);
+        let outdated = backups.len().saturating_sub(MAX_BACKUPS - 1);
+        for backup in &backups[..outdated] {
+            self.changes.push(Change::Remove {
+                path: backup.clone(),
+                backup: None,
+            });
+        }
+        let timestamp = &self.timestamp;
+        let backup = (0..)
+            .map(|i| match i {
+                0 => sibling(path, &format!("{timestamp}.bak")),
+                i => sibling(path, &format!("{timestamp}-{i}.bak")),
+            })
+            .find(|backup| {
+                !backup.exists()
+                    && !self.changes.iter().any(|c| match c {
+                        Change::Write { backup: b, .. } | Change::Remove { backup: b, .. } => {
+                            b.as_ref() == Some(backup)
+                        }
+                        Change::RemoveDir(_) => false,
+                    })
+            })
+            .unwrap();
+        Some(backup)
+    }
+
+    /// Removes the query directories of glob languages which were generated
+    /// for this project previously, but aren't anymore. That happens when a
+    /// path glob section is renamed or removed from the .editorconfig.
+    /// Directories which are still used by another project are kept, but
+    /// they no longer belong to this one.
+    fn prune_glob_lang_dirs(&mut self, manifest: &ec2hx::manifest::Manifest, project: &Path) {
+        let Some(entry) = manifest.project(project) else {
+            return;
+        };
+        for dir in &entry.dirs {
+            let is_glob_lang_dir = dir
+                .file_name()
+                .is_some_and(|name| name.to_string_lossy().starts_with("ec2hx-glob-lang-"));
+            if !is_glob_lang_dir || self.generated_dirs.contains(dir) {
+                continue;
+            }
+            self.stale_dirs.push(dir.clone());
+            if manifest.is_used_by_other(project, dir) || !dir.exists() {
+                continue;
+            }
+            for file in entry.files.iter().filter(|f| f.starts_with(dir)) {
+                if file.exists() && !manifest.is_used_by_other(project, file) {
+                    // generated queries are not worth a backup
+                    self.changes.push(Change::Remove {
+                        path: file.clone(),
+                        backup: None,
+                    });
+                }
+            }
+            self.changes.push(Change::RemoveDir(dir.clone()));
+        }
+    }
+
+    /// Adds the generated files and directories to the manifest, such that
+    /// `ec2hx clean` can remove them later.
+    fn record_in_manifest(&mut self, manifest: &mut ec2hx::manifest::Manifest, project: &Path) {
+        let entry = manifest.project_mut(project);
+
+        let removed = self
+            .changes
+            .iter()
+            .filter_map(|c| match c {
+                Change::Remove { path, .. } => Some(absolute(path)),
+                Change::Write { .. } | Change::RemoveDir(_) => None,
+            })
+            .collect::<Vec<_>>();
+        // previously generated files stay recorded until they are removed
+        entry.files.retain(|f| {
+            f.exists() && !removed.contains(f) && !self.stale_dirs.iter().any(|d| f.starts_with(d))
+        });
+        entry
+            .dirs
+            .retain(|d| d.exists() && !self.stale_dirs.contains(d));
+        entry
+            .files
+            .extend(self.generated_files.iter().map(|f| absolute(f)));
+        entry
+            .dirs
+            .extend(self.generated_dirs.iter().map(|d| absolute(d)));
+
+        let path = manifest_path();
+        let contents = manifest.to_toml();
+        if fs::read_to_string(&path).is_ok_and(|prev| prev == contents) {
+            return;
+        }
+        // The manifest is not backed up, there would be a new backup with
+        // every run.
+        self.changes.push(Change::Write {
+            path,
+            contents,
+            backup: None,
+        });
+    }
+
+    fn print(&self) {
+        if self.changes.is_empty() && !self.reload_hx {
+            println!("Nothing to do.");
+            return;
+        }
+        for change in &self.changes {
+            match change {
+                Change::Write { path, backup, .. } => match backup {
+                    Some(backup) => println!(
+                        "would change {} (backup: {})",
+                        path.display(),
+                        backup.display()
+                    ),
+                    None if path.exists() => println!("would change {}", path.display()),
+                    None => println!("would create {}", path.display()),
+                },
+                Change::Remove {
+                    path,
+                    backup: Some(backup),
+                } => println!(
+                    "would remove {} (backup: {})",
+                    path.display(),
+                    backup.display()
+                ),
+                Change::Remove { path, backup: None } => {
+                    println!("would remove {}", path.display())
+                }
+                Change::RemoveDir(path) => println!("would remove {}", path.display()),
+            }
+        }
+        if self.reload_hx {
+            println!("would signal running hx processes to reload their config");
+        }
+    }
+
+    /// Applies all changes or none of them. The new contents are staged in
+    /// temporary files next to their destination first, which are then renamed
+    /// over it. Overwritten and removed files are kept as timestamped backups,
+    /// see [Plan::backup_path].
+    /// If anything fails, the changes made so far are rolled back.
+    fn apply(self) {
+        let mut transaction = Transaction::default();
+        if let Err((path, err)) = transaction.run(&self.changes) {
+            transaction.roll_back();
+            println!("ERROR: Failed to update {}: {err}", path.display());
+            println!("       No files were changed.");
+            exit(1);
+        }
+        if self.reload_hx {
+            let _ = std::process::Command::new("killall")
+                .args(["-USR1", "hx"])
+                .status();
+        }
+    }
+}
+
+/// Keeps track of what [Plan::apply] did, so it can be undone.
+#[derive(Debug, Default)]
+struct Transaction {
+    created_dirs: Vec<PathBuf>,
+    staged: Vec<PathBuf>,
+    /// Removed and overwritten files without a backup. They are only deleted
+    /// for good once everything else succeeded.
+    trash: Vec<PathBuf>,
+    done: Vec<Undo>,
+}
+
+#[derive(Debug)]
+enum Undo {
+    Remove(PathBuf),
+    Restore { backup: PathBuf, path: PathBuf },
+}
+
+impl Transaction {
+    fn run(&mut self, changes: &[Change]) -> Result<(), (PathBuf, io::Error)> {
+        let with_path = |path: &Path| {
+            let path = path.to_path_buf();
+            move |err| (path, err)
+        };
+
+        for change in changes {
+            if let Change::Write { path, contents, .. } = change {
+                if let Some(dir) = path.parent() {
+                    self.create_dir_all(dir).map_err(with_path(dir))?;
+                }
+                let staged = sibling(path, "ec2hx-staged");
+                self.staged.push(staged.clone());
+                fs::write(&staged, contents).map_err(with_path(path))?;
+            }
+        }
+
+        for change in changes {
+            match change {
+                Change::Write { path, backup, .. } => {
+                    match backup {
+                        Some(backup) => {
+                            fs::copy(path, backup).map_err(with_path(path))?;
+                            self.done.push(Undo::Restore {
+                                backup: backup.clone(),
+                                path: path.clone(),
+                            });
+                        }
+                        None if path.exists() => {
+                            // moved aside, such that it can be restored
+                            let trash = sibling(path, "ec2hx-replaced");
+                            fs::rename(path, &trash).map_err(with_path(path))?;
+                            self.trash.push(trash.clone());
+                            self.done.push(Undo::Restore {
+                                backup: trash,
+                                path: path.clone(),
+                            });
+                        }
+                        None => self.done.push(Undo::Remove(path.clone())),
+                    }
+                    fs::rename(sibling(path, "ec2hx-staged"), path).map_err(with_path(path))?;
+                }
+                Change::Remove { path, backup } => {
+                    let backup = match backup {
+                        Some(backup) => backup.clone(),
+                        None => {
+                            let trash = sibling(path, "ec2hx-removed");
+                            self.trash.push(trash.clone());
+                            trash
+                        }
+                    };
+                    fs::rename(path, &backup).map_err(with_path(path))?;
+                    self.done.push(Undo::Restore {
+                        backup,
+                        path: path.clone(),
+                    });
+                }
+                Change::RemoveDir(_) => {}
+            }
+        }
+
+        // Nothing can fail anymore, directories which aren't empty are kept.
+        for trash in &self.trash {
+            let _ = fs::remove_file(trash);
+        }
+        for change in changes {
+            if let Change::RemoveDir(dir) = change {
+                let _ = fs::remove_dir(dir);
+            }
+        }
+        Ok(())
+    }
+
+    /// like [fs::create_dir_all], but remembers the created directories
+    fn create_dir_all(&mut self, dir: &Path) -> io::Result<()> {
+        let missing = dir
+            .ancestors()
+            .take_while(|d| !d.as_os_str().is_empty() && !d.exists())
+            .collect::<Vec<_>>();
+        for dir in missing.into_iter().rev() {
+            fs::create_dir(dir)?;
+            self.created_dirs.push(dir.into());
+        }
+        Ok(())
+    }
+
+    /// Best effort, there's nothing left to do about errors at this point.
+    fn roll_back(self) {
+        for undo in self.done.into_iter().rev() {
+            let _ = match undo {
+                Undo::Remove(path) => fs::remove_file(path),
+                Undo::Restore { backup, path } => fs::rename(backup, path),
+            };
+        }
+        for staged in self.staged {
+            let _ = fs::remove_file(staged);
+        }
+        for dir in self.created_dirs.into_iter().rev() {
+            let _ = fs::remove_dir(dir);
+        }
+    }
+}
+
+/// Removes the files and directories generated for the current project, or
+/// all projects, according to the manifest. Paths which are also used by
+/// another project are kept.
+fn clean(helix_dir: &Path, all: bool, dry_run: bool) {
+    let mut manifest = read_manifest();
+    let projects = if all {
+        std::mem::take(&mut manifest.projects)
+    } else {
+        manifest
+            .remove_project(&project_dir(helix_dir))
+            .into_iter()
+            .collect()
     };
+    if projects.is_empty() {
+        println!("Nothing to clean.");
+        return;
+    }
 
-    let Some(fetched_languages) = fetch_languages(&hx_version_hash) else {
-        return stale_cache;
+    for project in &projects {
+        let is_used_by_other = |path: &Path| manifest.is_used_by_other(&project.path, path);
+        for file in &project.files {
+            if !file.exists() || is_used_by_other(file) {
+                continue;
+            }
+            if dry_run {
+                println!("would remove {}", file.display());
+            } else if let Err(err) = fs::remove_file(file) {
+                println!("WARN: Failed to remove {}: {err}", file.display());
+            }
+        }
+        // children come after their parents, so they are removed first
+        for dir in project.dirs.iter().rev() {
+            if !dir.exists() || is_used_by_other(dir) {
+                continue;
+            }
+            if dry_run {
+                println!("would remove {} if it's empty", dir.display());
+            } else {
+                // fails if the user added files, which are left alone
+                let _ = fs::remove_dir(dir);
+            }
+        }
+    }
+
+    if dry_run {
+        return;
+    }
+    let path = manifest_path();
+    let result = if manifest.projects.is_empty() {
+        fs::remove_file(&path)
+    } else {
+        fs::write(&path, manifest.to_toml())
     };
+    if let Err(err) = result {
+        println!("WARN: Failed to update {}: {err}", path.display());
+    }
+}
 
-    // try to update cache, ignore failure
-    let _ = std::fs::create_dir_all(cache_path.parent().unwrap());
-    let _ = std::fs::write(cache_path, &fetched_languages);
+/// Where the configuration is written. That's the .helix directory in the
+/// workspace root, unless --output-dir is used.
+fn helix_dir(args: &CliArgs) -> PathBuf {
+    if let Some(dir) = &args.output_dir {
+        return dir.clone();
+    }
+    let root = workspace_root(args);
+    if std::env::current_dir().is_ok_and(|cwd| cwd == root) {
+        ".helix".into()
+    } else {
+        root.join(".helix")
+    }
+}
 
-    Some(fetched_languages)
+/// the workspace root of the .editorconfig, where Helix looks for .helix
+fn workspace_root(args: &CliArgs) -> PathBuf {
+    find_workspace(&editorconfig_dir(args))
 }
 
-fn get_hx_version_hash() -> Option<String> {
-    let output = std::process::Command::new("hx")
-        .arg("--version")
-        .output()
-        .ok()?
-        .stdout;
-    let output = std::str::from_utf8(&output).ok()?;
-    let hash = output.split(['(', ')']).nth(1)?;
-
-    if !hash
-        .chars()
-        .all(|c| c.is_ascii_digit() || ('a'..='f').contains(&c))
-    {
-        // doesn't look like a hash
+/// Finds the workspace root like Helix does. That's the closest directory
+/// with a version control directory or an existing .helix directory. If there
+/// is none, it's the start directory itself. The markers are exactly the ones
+/// of Helix, see `find_workspace` in helix-loader/src/lib.rs.
+fn find_workspace(start: &Path) -> PathBuf {
+    let markers = [".git", ".svn", ".jj", ".helix"];
+    start
+        .ancestors()
+        .find(|dir| markers.iter().any(|m| dir.join(m).exists()))
+        .unwrap_or(start)
+        .into()
+}
+
+/// the directory of the .editorconfig, the current one if it's read from stdin
+fn editorconfig_dir(args: &CliArgs) -> PathBuf {
+    let cwd = std::env::current_dir().expect("failed to get the current directory");
+    let dir = match &args.editorconfig {
+        Some(path) if path != Path::new("-") => match absolute(path).parent() {
+            Some(dir) => dir.into(),
+            None => cwd,
+        },
+        _ => cwd,
+    };
+    dir.canonicalize().unwrap_or(dir)
+}
+
+/// The location of the .editorconfig relative to the project, if it's in a
+/// subdirectory. Path globs need to be rewritten relative to the project.
+fn path_prefix(args: &CliArgs) -> Option<String> {
+    let dir = editorconfig_dir(args);
+    let relative = dir.strip_prefix(project_dir(&helix_dir(args))).ok()?;
+    if relative.as_os_str().is_empty() {
         return None;
     }
-    Some(hash.into())
+    let components = relative
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>();
+    Some(components.join("/"))
+}
+
+/// The path of a generated file relative to the project, e.g.
+/// ".helix/languages.toml". Patches are applied from the project directory.
+fn project_relative(name: &str) -> String {
+    let path = absolute(Path::new(name));
+    let dir = path.parent().and_then(Path::file_name).unwrap_or_default();
+    let file = path.file_name().unwrap_or_default();
+    format!("{}/{}", dir.to_string_lossy(), file.to_string_lossy())
+}
+
+/// the directory containing .helix, as recorded in the manifest
+fn project_dir(helix_dir: &Path) -> PathBuf {
+    let dir = absolute(helix_dir);
+    let dir = dir.parent().unwrap_or(&dir);
+    dir.canonicalize().unwrap_or(dir.into())
+}
+
+fn absolute(path: &Path) -> PathBuf {
+    std::path::absolute(path).unwrap_or(path.into())
+}
+
+fn manifest_path() -> PathBuf {
+    let strategy = choose_base_strategy().expect("Unable to find the data directory!");
+    strategy.data_dir().join("ec2hx").join("manifest.toml")
+}
+
+fn read_manifest() -> ec2hx::manifest::Manifest {
+    let path = manifest_path();
+    let Ok(content) = fs::read_to_string(&path) else {
+        return Default::default();
+    };
+    ec2hx::manifest::Manifest::parse(&content).unwrap_or_else(|_| {
+        println!("WARN: {} is invalid, starting over.", path.display());
+        Default::default()
+    })
 }
 
-enum CacheContent {
-    Fresh(String),
-    Stale(String),
+/// e.g. "languages.toml" -> "languages.toml.bak"
+fn sibling(path: &Path, extension: &str) -> PathBuf {
+    let mut file_name = path.file_name().unwrap_or_default().to_os_string();
+    file_name.push(".");
+    file_name.push(extension);
+    path.with_file_name(file_name)
 }
 
-fn read_cache(cache_path: &Path) -> Option<CacheContent> {
-    let content = std::fs::read_to_string(cache_path).ok()?;
+/// how many backups are kept per file
+const MAX_BACKUPS: usize = 3;
+
+/// The existing backups of a file, the oldest first.
+fn backups(path: &Path) -> Vec<PathBuf> {
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut backups = entries
+        .flatten()
+        .filter_map(|entry| {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let stamp = name
+                .strip_prefix(&*file_name)?
+                .strip_prefix('.')?
+                .strip_suffix(".bak")?;
+            // e.g. "20250224-153000" or "20250224-153000-1"
+            let (timestamp, counter) = match stamp.get(15..) {
+                Some("") => (stamp, 0),
+                Some(counter) => (&stamp[..15], counter.strip_prefix('-')?.parse().ok()?),
+                None => return None,
+            };
+            if !timestamp.chars().all(|c| c.is_ascii_digit() || c == '-') {
+                return None;
+            }
+            Some(((timestamp.to_string(), counter), path.with_file_name(name)))
+        })
+        .collect::<Vec<_>>();
+    backups.sort();
+    backups.into_iter().map(|(_, backup)| backup).collect()
+}
+
+/// the current UTC time, e.g. "20250224-153000"
+fn timestamp() -> String {
+    let secs = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    let (days, secs) = (secs / 86400, secs % 86400);
+
+    // civil_from_days, see <https://howardhinnant.github.io/date_algorithms.html>
+    let z = days + 719468;
+    let era = z / 146097;
+    let doe = z % 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + u64::from(month <= 2);
+
+    format!(
+        "{year:04}{month:02}{day:02}-{:02}{:02}{:02}",
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60
+    )
+}
 
-    let cache_metadata = std::fs::metadata(cache_path).ok()?;
-    let mtime = cache_metadata.modified().ok()?;
-    let one_week = Duration::from_secs(60 * 60 * 24 * 7);
+/// Runtime directories where distributions commonly install Helix.
+const DISTRO_RUNTIME_DIRS: &[&str] = &[
+    "/usr/lib/helix/runtime",
+    "/usr/lib64/helix/runtime",
+    "/usr/share/helix/runtime",
+    "/usr/local/lib/helix/runtime",
+    "/usr/local/share/helix/runtime",
+    "/opt/homebrew/opt/helix/libexec/runtime",
+    "/usr/local/opt/helix/libexec/runtime",
+];
 
-    if mtime.elapsed().ok()? < one_week {
-        Some(CacheContent::Fresh(content))
+/// Looks for the languages.toml of the installed Helix on this machine, so
+/// the network is only needed as a last resort. Helix compiles it into the
+/// binary, but a source checkout has it next to the runtime directory and
+/// some packages ship it inside.
+fn read_local_languages() -> Option<String> {
+    let mut runtime_dirs = Vec::new();
+    runtime_dirs.extend(std::env::var_os("HELIX_RUNTIME").map(PathBuf::from));
+    runtime_dirs.extend(
+        hx_health()
+            .map(runtime_dirs_from_health)
+            .unwrap_or_default(),
+    );
+    // usually a symlink into a source checkout
+    runtime_dirs.push(helix_config_dir().join("runtime"));
+    runtime_dirs.extend(DISTRO_RUNTIME_DIRS.iter().map(PathBuf::from));
+
+    languages_toml_candidates(&runtime_dirs, &helix_config_dir())
+        .into_iter()
+        .filter_map(|path| fs::read_to_string(path).ok())
+        .find(|languages| ec2hx::parse::try_languages(languages).is_ok())
+}
+
+/// The files which may be the languages.toml of Helix, given its runtime
+/// directories. The languages.toml next to a runtime directory is only used
+/// in a source checkout of Helix. Nothing in the config directory is used,
+/// the languages.toml there only has the overrides of the user.
+fn languages_toml_candidates(runtime_dirs: &[PathBuf], config_dir: &Path) -> Vec<PathBuf> {
+    let config_dir = fs::canonicalize(config_dir).unwrap_or_else(|_| config_dir.into());
+    runtime_dirs
+        .iter()
+        .filter_map(|dir| fs::canonicalize(dir).ok())
+        .flat_map(|dir| {
+            let checkout = dir
+                .parent()
+                .filter(|parent| parent.join("helix-term").is_dir())
+                .map(|parent| parent.join("languages.toml"));
+            [Some(dir.join("languages.toml")), checkout]
+        })
+        .flatten()
+        .filter(|path| fs::canonicalize(path).is_ok_and(|path| !path.starts_with(&config_dir)))
+        .collect()
+}
+
+/// Parses the runtime directories from the output of `hx --health`. Older
+/// versions of Helix only report a single one.
+fn runtime_dirs_from_health(health: &str) -> Vec<PathBuf> {
+    health
+        .lines()
+        .filter_map(|line| {
+            line.strip_prefix("Runtime directories: ")
+                .or_else(|| line.strip_prefix("Runtime directory: "))
+        })
+        .flat_map(|dirs| dirs.split(';'))
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .collect()
+}
+
+/// The output of `hx --health`, which takes a while, so it's only run once.
+fn hx_health() -> Option<&'static str> {
+    static HEALTH: OnceLock<Option<String>> = OnceLock::new();
+    HEALTH
+        .get_or_init(|| {
+            std::process::Command::new("hx")
+                .arg("--health")
+                .output()
+                .ok()
+                .map(|output| String::from_utf8_lossy(&output.stdout).into_owned())
+        })
+        .as_deref()
+}
+
+/// Finds the languages.toml of the installed Helix version: in a local
+/// runtime, bundled with ec2hx, in the cache or on GitHub. If none of them is
+/// available, the bundled one is used anyway and a warning says so.
+fn find_languages(
+    hx_version: Option<&HxVersion>,
+    cache: &ec2hx::fetch::Cache,
+    source: &ec2hx::fetch::Source,
+) -> (String, Option<String>) {
+    if let Some(languages) = read_local_languages() {
+        return (languages, None);
+    }
+    let bundled = ec2hx::DEFAULT_LANGUAGES.to_string();
+    let bundled_release = HxVersion::bundled().release.unwrap_or_default();
+    let Some(hx_version) = hx_version else {
+        let warning = format!(
+            "Failed to determine the version of Helix, \
+             using the languages.toml of Helix {bundled_release} bundled with ec2hx."
+        );
+        return (bundled, Some(warning));
+    };
+    if hx_version.is_bundled() {
+        return (bundled, None);
+    }
+    match ec2hx::fetch::fetch_and_cache(cache, source, hx_version.git_ref()) {
+        Some(languages) => (languages, None),
+        None => {
+            let warning = format!(
+                "The languages.toml of Helix {hx_version} is not available, \
+                 using the one of Helix {bundled_release} bundled with ec2hx."
+            );
+            (bundled, Some(warning))
+        }
+    }
+}
+
+/// Reads the languages.toml given with --languages-file, which must be valid.
+fn read_languages_file(path: &Path) -> Vec<ec2hx::HelixLangCfg> {
+    let Ok(content) = fs::read_to_string(path) else {
+        println!("ERROR: Failed to read {}.", path.display());
+        exit(1);
+    };
+    match ec2hx::parse::try_languages(&content) {
+        Ok(languages) => languages,
+        Err(err) => {
+            println!(
+                "ERROR: {} is not a valid languages.toml: {err}",
+                path.display()
+            );
+            exit(1);
+        }
+    }
+}
+
+fn cache(
+    cmd: CacheCommand,
+    cache: &ec2hx::fetch::Cache,
+    source: &ec2hx::fetch::Source,
+    dry_run: bool,
+) {
+    use ec2hx::fetch::EntryState;
+
+    match cmd {
+        CacheCommand::List => {
+            let entries = cache.entries();
+            if entries.is_empty() {
+                println!("The cache is empty.");
+            }
+            let width = entries.iter().map(|e| e.git_ref.len()).max().unwrap_or(0);
+            for entry in entries {
+                let age = entry
+                    .age
+                    .map(format_age)
+                    .unwrap_or_else(|| "unknown".into());
+                let state = match entry.state {
+                    EntryState::Fresh => "valid",
+                    EntryState::Stale => "valid, stale",
+                    EntryState::Invalid => "invalid",
+                    EntryState::Quarantined => "invalid, quarantined",
+                };
+                println!("{:<width$}  {age:<11}  {state}", entry.git_ref);
+            }
+        }
+        CacheCommand::Clear if dry_run => println!("would remove {}", cache.dir.display()),
+        CacheCommand::Clear => {
+            if let Err(err) = cache.clear() {
+                println!("ERROR: Failed to remove {}: {err}", cache.dir.display());
+                exit(1);
+            }
+        }
+        CacheCommand::Prefetch { version } => {
+            let Some(git_ref) = version.or_else(|| Some(get_hx_version()?.git_ref().into())) else {
+                println!("ERROR: Failed to determine the version of Helix.");
+                println!("       Please specify the version to prefetch.");
+                exit(1);
+            };
+            if source.offline {
+                println!("ERROR: Prefetching needs the network, but ec2hx is in offline mode.");
+                exit(1);
+            }
+            let url = source.url(&git_ref);
+            if dry_run {
+                println!("would download {url}");
+                println!("would create {}", cache.path(&git_ref).display());
+                return;
+            }
+            let languages = match source.fetch(&git_ref) {
+                Ok(languages) => languages,
+                Err(err) => {
+                    println!("ERROR: Failed to download {url}: {err}");
+                    exit(1);
+                }
+            };
+            if let Err(err) = cache.write(&git_ref, &languages) {
+                let path = cache.path(&git_ref);
+                println!("ERROR: Failed to write {}: {err}", path.display());
+                exit(1);
+            }
+            println!("Cached the languages.toml of Helix {git_ref}.");
+        }
+        CacheCommand::Path => println!("{}", cache.dir.display()),
+    }
+}
+
+/// A rough, human readable age like "3 days".
+fn format_age(age: std::time::Duration) -> String {
+    let secs = age.as_secs();
+    let (n, unit) = if secs < 60 {
+        (secs, "second")
+    } else if secs < 60 * 60 {
+        (secs / 60, "minute")
+    } else if secs < 60 * 60 * 24 {
+        (secs / (60 * 60), "hour")
     } else {
-        Some(CacheContent::Stale(content))
+        (secs / (60 * 60 * 24), "day")
+    };
+    match n {
+        1 => format!("{n} {unit}"),
+        _ => format!("{n} {unit}s"),
     }
 }
 
-fn fetch_languages(version_hash: &str) -> Option<String> {
-    reqwest::blocking::Client::builder()
-        .timeout(std::time::Duration::from_secs(1))
-        .build()
-        .ok()?
-        .get(format!(
-            "https://raw.githubusercontent.com/helix-editor/helix/{version_hash}/languages.toml"
-        ))
-        .send()
+fn languages_cache_dir() -> PathBuf {
+    let strategy = choose_base_strategy().expect("Unable to find the config directory!");
+    strategy.cache_dir().join("ec2hx")
+}
+
+fn get_hx_version() -> Option<HxVersion> {
+    let output = std::process::Command::new("hx")
+        .arg("--version")
+        .output()
         .ok()?
-        .text()
-        .ok()
+        .stdout;
+    HxVersion::parse(std::str::from_utf8(&output).ok()?)
 }
 
 /// copied from helix-loader/src/lib.rs to match Helix' behavior
@@ -254,7 +1415,13 @@ fn helix_config_dir() -> std::path::PathBuf {
 fn read_user_languages() -> Option<Vec<ec2hx::HelixLangCfg>> {
     let path = helix_config_dir().join("languages.toml");
     let content = std::fs::read_to_string(&path).ok()?;
-    Some(ec2hx::parse::languages(&content))
+    match ec2hx::parse::user_languages(&content) {
+        Ok(languages) => Some(languages),
+        Err(err) => {
+            println!("WARN: {} is invalid, it's ignored: {err}", path.display());
+            None
+        }
+    }
 }
 
 /// This function parses the output of `hx --health` to determine for which
@@ -271,11 +1438,7 @@ fn set_has_formattes_from_hx_health(
         l.set_has_formatter(false);
     }
 
-    let table = std::process::Command::new("hx")
-        .arg("--health")
-        .output()
-        .ok()
-        .map(|output| String::from_utf8_lossy(&output.stdout).to_string())?;
+    let table = hx_health()?;
 
     let mut lines = table.lines();
     let mut lsp_offset = 0;
@@ -335,54 +1498,616 @@ fn set_has_formattes_from_hx_health(
     Some(languages)
 }
 
-fn try_write_toml(name: &str, contents: String) {
-    if contents
-        .lines()
-        .all(|line| line.is_empty() || line.starts_with('#'))
-    {
-        return;
+fn parse_language_rulers(s: &str) -> Result<(String, Vec<ec2hx::RulerColumn>), String> {
+    let (lang, columns) = s
+        .split_once('=')
+        .ok_or_else(|| format!("expected <language>=<columns>, got {s:?}"))?;
+    let columns = columns
+        .split(',')
+        .filter(|c| !c.trim().is_empty())
+        .map(str::parse)
+        .collect::<Result<_, _>>()?;
+    Ok((lang.trim().into(), columns))
+}
+
+/// Lists the files in the working tree, relative to its root. If possible,
+/// git is used to skip ignored files. Otherwise, only hidden directories are
+/// skipped.
+fn list_project_files(root: &Path) -> Vec<PathBuf> {
+    let git_output = std::process::Command::new("git")
+        .args(["ls-files", "--cached", "--others", "--exclude-standard"])
+        .current_dir(root)
+        .output()
+        .ok()
+        .filter(|output| output.status.success());
+    if let Some(output) = git_output {
+        return String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .map(PathBuf::from)
+            .collect();
+    }
+
+    let mut files = Vec::new();
+    let mut dirs = vec![root.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        let Ok(entries) = fs::read_dir(&dir) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let Ok(file_type) = entry.file_type() else {
+                continue;
+            };
+            let path = dir.join(entry.file_name());
+            if !file_type.is_dir() {
+                files.push(path.strip_prefix(root).unwrap_or(&path).to_path_buf());
+            } else if !entry.file_name().to_string_lossy().starts_with('.') {
+                dirs.push(path);
+            }
+        }
+    }
+    files
+}
+
+/// where the previously generated configuration of a file is kept
+fn base_path(name: &str) -> PathBuf {
+    let path = Path::new(name);
+    path.parent()
+        .unwrap_or(Path::new("."))
+        .join(".ec2hx")
+        .join(path.file_name().expect("file name is missing"))
+}
+
+/// Compares the generated configuration with an existing file and prints a
+/// patch if they differ. Manual additions to the file are not a difference,
+/// only entries which don't match the generated configuration. Returns whether
+/// the file is up to date.
+///
+/// Without the previously generated configuration, manual additions can't be
+/// told apart from entries which are not generated anymore. The file has to
+/// match the generated configuration exactly in that case.
+fn check_toml(name: &str, contents: &str) -> bool {
+    let exists = Path::new(name).exists();
+    let prev_contents = fs::read_to_string(name).unwrap_or_default();
+    let base = fs::read_to_string(base_path(name)).ok();
+    let expected = match base {
+        _ if prev_contents.is_empty()
+            && contents
+                .lines()
+                .all(|line| line.is_empty() || line.starts_with('#')) =>
+        {
+            // such a file isn't written
+            prev_contents.clone()
+        }
+        Some(base) => match ec2hx::merge::merge(Some(&base), &prev_contents, contents) {
+            Ok(merged) => merged.proposed,
+            Err(_) => contents.into(),
+        },
+        None => contents.into(),
+    };
+    let patch = ec2hx::diff::unified(
+        &project_relative(name),
+        exists.then_some(prev_contents.as_str()),
+        &expected,
+    );
+    print!("{patch}");
+    patch.is_empty()
+}
+
+/// The command line options recorded in the stamp. Paths and options which
+/// don't influence the generated configuration are left out, the stamp should
+/// be the same on every machine. The options are written in a canonical form,
+/// such that equivalent invocations result in the same stamp, e.g. "--rulers"
+/// and "--ruler-columns +0" or "--only-languages go,rust" and
+/// "--only-languages rust --only-languages go".
+fn stamp_options(args: &CliArgs) -> String {
+    let list = |values: &[String]| {
+        let mut values = values.to_vec();
+        values.sort();
+        values.dedup();
+        values.join(",")
+    };
+    let columns = |columns: &[ec2hx::RulerColumn]| {
+        let columns = columns.iter().map(ToString::to_string);
+        columns.collect::<Vec<_>>().join(",")
+    };
+    let defaults = ec2hx::Options::default();
+
+    let mut options = Vec::new();
+    if args.auto {
+        options.push("--auto".into());
+    }
+    if args.default_indent_size != defaults.default_indent_size {
+        options.push(format!(
+            "--default-indent-size {}",
+            args.default_indent_size
+        ));
+    }
+    if !args.exclude_languages.is_empty() {
+        options.push(format!(
+            "--exclude-languages {}",
+            list(&args.exclude_languages)
+        ));
+    }
+    if !args.fallback_globs.is_empty() {
+        options.push(format!("--fallback-globs {}", list(&args.fallback_globs)));
+    }
+    // the last columns of a language win, like in the generated configuration
+    let language_rulers = args
+        .language_rulers
+        .iter()
+        .cloned()
+        .collect::<BTreeMap<_, _>>();
+    for (lang, lang_columns) in language_rulers {
+        options.push(format!(
+            "--language-rulers {lang}={}",
+            columns(&lang_columns)
+        ));
+    }
+    if args.minimal {
+        options.push("--minimal".into());
+    }
+    if !args.only_languages.is_empty() {
+        options.push(format!("--only-languages {}", list(&args.only_languages)));
+    }
+    if args.portable {
+        options.push("--portable".into());
+    }
+    let ruler_columns = ruler_columns(args);
+    if !ruler_columns.is_empty() {
+        options.push(format!("--ruler-columns {}", columns(&ruler_columns)));
+    }
+    if args.ruler_position != defaults.rulers.position {
+        options.push(format!("--ruler-position {}", args.ruler_position));
     }
-    if let Ok(prev_contents) = fs::read_to_string(name) {
-        if prev_contents == contents {
+    if args.soft_wrap {
+        options.push("--soft-wrap".into());
+    }
+    options.join(" ")
+}
+
+/// The global ruler columns, "--rulers" is short for "--ruler-columns +0".
+fn ruler_columns(args: &CliArgs) -> Vec<ec2hx::RulerColumn> {
+    if !args.ruler_columns.is_empty() {
+        args.ruler_columns.clone()
+    } else if args.rulers {
+        ec2hx::Rulers::after_limit().columns
+    } else {
+        Vec::new()
+    }
+}
+
+/// Writes generated configuration to a file. If the file already exists, the
+/// changes are merged into it. The previously generated configuration is kept
+/// in a `.ec2hx` directory next to the file, to tell manual changes apart from
+/// changes of the .editorconfig.
+///
+/// If the file was generated by another version of ec2hx, for another
+/// version of Helix or with other options, conflicts are resolved in favor of the generated
+/// configuration instead of writing a patch. The previous file is backed up.
+///
+/// In interactive mode, the user reviews conflicts instead of getting a patch.
+fn try_write_toml(plan: &mut Plan, name: &str, contents: String, interactive: bool) {
+    let base_path = base_path(name);
+
+    let Ok(prev_contents) = fs::read_to_string(name) else {
+        if contents
+            .lines()
+            .all(|line| line.is_empty() || line.starts_with('#'))
+        {
             return;
         }
-        let name_new = &format!("{name}.new");
-        let name_patch = &format!("{name}.patch");
+        plan.write(name, &contents);
+        plan.write(base_path, contents);
+        return;
+    };
+    let base = fs::read_to_string(&base_path).ok();
 
-        println!("WARN: {name} already exists.");
-        if fs::write(name_new, &contents).is_err() {
-            panic!("failed to write {name_new}");
+    // Without a base, every difference is a conflict. If the file is
+    // outdated, the conflicts are resolved in favor of the generated
+    // configuration below. Entries which only exist in the file are kept,
+    // there's no telling whether they were generated or added by the user.
+    let outdated_by = outdated_by(&prev_contents, &contents);
+    let has_base = base.is_some();
+
+    let merged = match ec2hx::merge::merge(base.as_deref(), &prev_contents, &contents) {
+        Ok(merged) => merged,
+        Err(_) if interactive => {
+            println!("{name} is not valid TOML, it can't be merged.");
+            plan.write(name, review(name, &prev_contents, &contents));
+            plan.write(base_path, contents);
+            plan.remove(format!("{name}.patch"));
+            return;
         }
+        Err(_) => {
+            println!("WARN: {name} is not valid TOML, it can't be updated.");
+            write_patch(plan, name, &prev_contents, &contents);
+            return;
+        }
+    };
+    let header = contents
+        .split_inclusive('\n')
+        .take_while(|line| line.starts_with('#'))
+        .collect::<String>();
+    let reviewed = outdated_by.is_none() && interactive && !merged.conflicts.is_empty();
+    let new_contents = match outdated_by {
+        Some(_) => merged.proposed.clone(),
+        None if reviewed => {
+            println!("Your changes to {name} conflict with the generated configuration:");
+            for conflict in &merged.conflicts {
+                println!("  - {conflict}");
+            }
+            review(name, &merged.content, &merged.proposed)
+        }
+        None => merged.content.clone(),
+    };
+    plan.write(name, ec2hx::stamp::replace_header(&new_contents, &header));
+    plan.write(base_path, &contents);
 
-        // Attempt to produce a diff against the existing file. This makes it
-        // easier for users to assess and apply the changes.
-        let create_diff = || -> Option<()> {
-            fs::write(name_new, &contents).ok()?;
+    let (was, were) = match plan.dry_run {
+        true => ("would be", "would be"),
+        false => ("was", "were"),
+    };
+    let name_patch = &format!("{name}.patch");
+    if let Some(outdated_by) = outdated_by {
+        println!("{name} was generated {outdated_by}, it {was} updated.");
+        if !merged.conflicts.is_empty() {
+            match has_base {
+                true => println!("WARN: Your changes to these entries {were} replaced:"),
+                // they may just be outdated
+                false => println!("WARN: These entries differed and {were} replaced:"),
+            }
+            for conflict in &merged.conflicts {
+                println!("      - {conflict}");
+            }
+            println!("      The previous version of the file {was} backed up.");
+        }
+        plan.remove(name_patch);
+        return;
+    }
+    if merged.conflicts.is_empty() || reviewed {
+        // an old patch would be outdated
+        plan.remove(name_patch);
+        return;
+    }
+    println!("WARN: Your changes to {name} {were} kept, but ec2hx would change them:");
+    for conflict in &merged.conflicts {
+        println!("      - {conflict}");
+    }
+    write_patch(plan, name, &merged.content, &merged.proposed);
+}
 
-            let diff_output = std::process::Command::new("diff")
-                .arg("--unified")
-                .arg(name)
-                .arg(name_new)
-                .output()
-                .ok()?;
-            fs::write(name_patch, diff_output.stdout).ok()?;
+/// Describes why a previously generated file is outdated, if it is. Files
+/// without the notice of ec2hx belong to the user and are never outdated.
+fn outdated_by(prev_contents: &str, contents: &str) -> Option<String> {
+    use ec2hx::stamp::{Stamp, is_generated};
 
-            // don't care if *.new file wasn't cleaned up
-            let _ = fs::remove_file(name_new);
-            Some(())
-        };
+    if !is_generated(prev_contents) {
+        return None;
+    }
+    let current = Stamp::parse(contents)?;
+    match Stamp::parse(prev_contents) {
+        None => Some("by an older version of ec2hx".into()),
+        Some(prev) if prev.is_current(&current) => None,
+        Some(prev) if prev.version != current.version => {
+            Some(format!("by version {} of ec2hx", prev.version))
+        }
+        Some(prev) if prev.options != current.options => Some("with other options".into()),
+        Some(_) => Some("for another version of Helix".into()),
+    }
+}
 
-        if create_diff().is_some() {
-            println!("      Writing the diff to {name_patch} instead.");
-            println!("      Run the following command to apply the patch:");
-            println!();
-            println!("      patch {name} < {name_patch}");
-            println!();
-        } else {
-            println!("      Writing to {name_new} instead.");
-            println!("      Compare and swap them manually if you like.");
+/// Asks about every hunk between the file and the proposed contents, like
+/// `git add -p`. Returns the file with the accepted hunks applied.
+fn review(name: &str, old: &str, new: &str) -> String {
+    let hunks = ec2hx::diff::hunks(old, new);
+    let mut accepted = Vec::new();
+    println!("--- a/{0}\n+++ b/{0}", project_relative(name));
+    'hunks: for (i, hunk) in hunks.iter().enumerate() {
+        print!("{hunk}");
+        loop {
+            print!(
+                "({}/{}) Apply this hunk to {name} [y,n,a,d,e,?]? ",
+                i + 1,
+                hunks.len()
+            );
+            let _ = stdout().flush();
+            let mut answer = String::new();
+            if stdin().read_line(&mut answer).unwrap_or(0) == 0 {
+                // end of input, keep the file as it is
+                println!();
+                break 'hunks;
+            }
+            match answer.trim() {
+                "y" => accepted.push(hunk.clone()),
+                "n" => {}
+                "a" => {
+                    accepted.extend(hunks[i..].iter().cloned());
+                    break 'hunks;
+                }
+                "d" => break 'hunks,
+                "e" => match edit_hunk(hunk) {
+                    Some(edited) => accepted.push(edited),
+                    None => {
+                        println!("Your edited hunk does not apply.");
+                        continue;
+                    }
+                },
+                _ => {
+                    println!("y - apply this hunk");
+                    println!("n - do not apply this hunk");
+                    println!("a - apply this hunk and all later hunks in the file");
+                    println!("d - do not apply this hunk or any of the later hunks in the file");
+                    println!("e - manually edit this hunk");
+                    println!("? - print help");
+                    continue;
+                }
+            }
+            break;
+        }
+    }
+    ec2hx::diff::apply(old, &accepted)
+}
+
+/// Lets the user edit a hunk in $VISUAL or $EDITOR.
+fn edit_hunk(hunk: &ec2hx::diff::Hunk) -> Option<ec2hx::diff::Hunk> {
+    let dir = private_temp_dir().ok()?;
+    let edited = edit_hunk_in(&dir, hunk);
+    let _ = fs::remove_dir_all(&dir);
+    edited
+}
+
+fn edit_hunk_in(dir: &Path, hunk: &ec2hx::diff::Hunk) -> Option<ec2hx::diff::Hunk> {
+    let path = dir.join("hunk.diff");
+    let text = format!(
+        "\
+# Manual hunk edit mode -- see bottom for a quick guide.
+{hunk}# ---
+# To remove '-' lines, make them ' ' lines (context).
+# To remove '+' lines, delete them.
+# Lines starting with # will be removed.
+"
+    );
+    // never follow a link someone else placed there
+    let mut file = fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&path)
+        .ok()?;
+    file.write_all(text.as_bytes()).ok()?;
+    drop(file);
+    let editor = std::env::var("VISUAL")
+        .or_else(|_| std::env::var("EDITOR"))
+        .unwrap_or_else(|_| "vi".into());
+    let mut editor = editor.split_whitespace();
+    let status = std::process::Command::new(editor.next()?)
+        .args(editor)
+        .arg(&path)
+        .status();
+    let edited = fs::read_to_string(&path);
+    if !status.ok()?.success() {
+        return None;
+    }
+    hunk.edit(&edited.ok()?)
+}
+
+/// Creates a new directory in the temporary directory, which only the current
+/// user can access. Other users can't predict or replace the files in it.
+fn private_temp_dir() -> io::Result<PathBuf> {
+    let nanos = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .subsec_nanos();
+    let mut builder = fs::DirBuilder::new();
+    #[cfg(unix)]
+    std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+    let mut attempt = 0;
+    loop {
+        let name = format!("ec2hx-{}-{nanos:08x}-{attempt}", std::process::id());
+        let dir = std::env::temp_dir().join(name);
+        match builder.create(&dir) {
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists && attempt < 100 => attempt += 1,
+            res => return res.map(|_| dir),
+        }
+    }
+}
+
+fn write_patch(plan: &mut Plan, name: &str, prev_contents: &str, contents: &str) {
+    let patch = ec2hx::diff::unified(&project_relative(name), Some(prev_contents), contents);
+    if patch.is_empty() {
+        return;
+    }
+    let name_patch = &format!("{name}.patch");
+    plan.write(name_patch, patch);
+    if plan.dry_run {
+        // the patch is listed with the other changes
+        println!("      The diff would be written to {name_patch}.");
+        return;
+    }
+    println!("      Writing the diff to {name_patch}.");
+    println!("      Run one of the following commands to apply the patch:");
+    println!();
+    let project = absolute(Path::new(name_patch));
+    let project = project
+        .parent()
+        .and_then(Path::parent)
+        .unwrap_or(Path::new("."));
+    if std::env::current_dir().is_ok_and(|cwd| cwd == project) {
+        println!("      git apply {name_patch}");
+        println!("      patch -p1 < {name_patch}");
+    } else {
+        let (project, name_patch) = (project.display(), absolute(Path::new(name_patch)));
+        println!("      git -C {project} apply {}", name_patch.display());
+        println!("      patch -d {project} -p1 < {}", name_patch.display());
+    }
+    println!();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn languages_toml_of_helix() {
+        let dir = std::env::temp_dir().join(format!("ec2hx-test-{}-runtime", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let config_dir = dir.join("config/helix");
+        fs::create_dir_all(config_dir.join("runtime")).unwrap();
+        fs::write(config_dir.join("languages.toml"), "[[language]]\n").unwrap();
+        let checkout = dir.join("src/helix");
+        fs::create_dir_all(checkout.join("helix-term")).unwrap();
+        fs::create_dir_all(checkout.join("runtime")).unwrap();
+        fs::write(checkout.join("languages.toml"), "[[language]]\n").unwrap();
+        let package = dir.join("usr/share/helix/runtime");
+        fs::create_dir_all(&package).unwrap();
+        fs::write(package.join("languages.toml"), "[[language]]\n").unwrap();
+        fs::write(dir.join("usr/share/helix/languages.toml"), "[[language]]\n").unwrap();
+
+        // the languages.toml of the user is never used
+        let runtime_dirs = [config_dir.join("runtime"), package.clone()];
+        let candidates = languages_toml_candidates(&runtime_dirs, &config_dir);
+        assert_eq!(
+            candidates,
+            [package.canonicalize().unwrap().join("languages.toml")]
+        );
+
+        // unless the runtime directory is a link into a checkout
+        fs::remove_dir(config_dir.join("runtime")).unwrap();
+        std::os::unix::fs::symlink(checkout.join("runtime"), config_dir.join("runtime")).unwrap();
+        let candidates = languages_toml_candidates(&[config_dir.join("runtime")], &config_dir);
+        assert_eq!(
+            candidates,
+            [checkout.canonicalize().unwrap().join("languages.toml")]
+        );
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn hunks_are_edited_in_a_private_directory() {
+        let dir = private_temp_dir().unwrap();
+        let other = private_temp_dir().unwrap();
+        assert_ne!(dir, other);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&dir).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o700);
         }
-    } else if fs::write(name, contents).is_err() {
-        panic!("failed to write {name}");
+
+        // an existing file is never written to
+        let path = dir.join("hunk.diff");
+        fs::write(&path, "not yours").unwrap();
+        let hunk = &ec2hx::diff::hunks("a\n", "b\n")[0];
+        assert_eq!(edit_hunk_in(&dir, hunk), None);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "not yours");
+
+        fs::remove_dir_all(dir).unwrap();
+        fs::remove_dir_all(other).unwrap();
+    }
+
+    #[test]
+    fn canonical_stamp_options() {
+        let stamp_options = |args: &[&str]| {
+            let args = CliArgs::try_parse_from(["ec2hx"].iter().chain(args)).unwrap();
+            stamp_options(&args)
+        };
+        let expected = "--minimal --only-languages go,rust --ruler-columns +0";
+        let equivalent: &[&[&str]] = &[
+            &["--only-languages", "rust,go", "--rulers", "--minimal"],
+            &[
+                "--minimal",
+                "--only-languages=go",
+                "--only-languages",
+                "rust",
+                "--ruler-columns=+0",
+            ],
+            &[
+                "--ruler-columns",
+                "+0",
+                "--minimal",
+                "--only-languages",
+                "go,rust,go",
+            ],
+        ];
+        for args in equivalent {
+            // options which don't influence the output are left out
+            let args = [*args, &["--dry-run", "--timeout", "5"]].concat();
+            assert_eq!(stamp_options(&args), expected);
+        }
+        assert_eq!(stamp_options(&["--default-indent-size", "4"]), "");
+        assert_eq!(
+            stamp_options(&[
+                "--language-rulers",
+                "md=+0",
+                "--language-rulers",
+                "md=80,-2"
+            ]),
+            "--language-rulers md=80,-2"
+        );
+    }
+
+    #[test]
+    fn roll_back_restores_files_without_backup() {
+        let dir = std::env::temp_dir().join(format!("ec2hx-test-{}-roll-back", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let existing = dir.join("languages.toml");
+        fs::write(&existing, "old").unwrap();
+        let created = dir.join("config.toml");
+        let removed = dir.join("highlights.scm");
+        fs::write(&removed, "queries").unwrap();
+        // the removed file can't be moved aside
+        fs::create_dir_all(sibling(&removed, "ec2hx-removed").join("blocker")).unwrap();
+
+        let changes = [
+            Change::Write {
+                path: existing.clone(),
+                contents: "new".into(),
+                backup: None,
+            },
+            Change::Write {
+                path: created.clone(),
+                contents: "new".into(),
+                backup: None,
+            },
+            Change::Remove {
+                path: removed.clone(),
+                backup: None,
+            },
+        ];
+        let mut transaction = Transaction::default();
+        let (path, _) = transaction.run(&changes).unwrap_err();
+        assert_eq!(path, removed);
+        transaction.roll_back();
+
+        assert_eq!(fs::read_to_string(&existing).unwrap(), "old");
+        assert!(!created.exists());
+        assert_eq!(fs::read_to_string(&removed).unwrap(), "queries");
+        assert!(!sibling(&existing, "ec2hx-replaced").exists());
+        assert!(!sibling(&existing, "ec2hx-staged").exists());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn replaced_files_are_deleted_after_success() {
+        let dir = std::env::temp_dir().join(format!("ec2hx-test-{}-replaced", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let existing = dir.join("languages.toml");
+        fs::write(&existing, "old").unwrap();
+
+        let changes = [Change::Write {
+            path: existing.clone(),
+            contents: "new".into(),
+            backup: None,
+        }];
+        Transaction::default().run(&changes).unwrap();
+
+        assert_eq!(fs::read_to_string(&existing).unwrap(), "new");
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+
+        fs::remove_dir_all(dir).unwrap();
     }
 }
@@ -0,0 +1,190 @@
+//! Keeps track of the files and directories generated by ec2hx, such that
+//! they can be cleaned up later. Some of them are outside the project, in the
+//! user's Helix config directory. That's why a single manifest is shared by
+//! all projects and records which project each path belongs to.
+
+use std::{
+    collections::BTreeSet,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use toml_edit::{ArrayOfTables, DocumentMut, Table};
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Manifest {
+    pub projects: Vec<Project>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Project {
+    /// absolute path of the project directory
+    pub path: PathBuf,
+    /// absolute paths of the generated files
+    pub files: BTreeSet<PathBuf>,
+    /// Absolute paths of the generated directories. They are only removed if
+    /// they are empty, the user may have added files to them.
+    pub dirs: BTreeSet<PathBuf>,
+}
+
+impl Manifest {
+    pub fn parse(s: &str) -> Result<Self, toml_edit::TomlError> {
+        let doc = DocumentMut::from_str(s)?;
+        let paths = |table: &Table, key: &str| {
+            table
+                .get(key)
+                .and_then(|a| a.as_array())
+                .into_iter()
+                .flatten()
+                .filter_map(|p| p.as_str())
+                .map(PathBuf::from)
+                .collect::<BTreeSet<_>>()
+        };
+        let projects = doc
+            .get("project")
+            .and_then(|p| p.as_array_of_tables())
+            .into_iter()
+            .flatten()
+            .filter_map(|table| {
+                Some(Project {
+                    path: table.get("path")?.as_str()?.into(),
+                    files: paths(table, "files"),
+                    dirs: paths(table, "dirs"),
+                })
+            })
+            .collect();
+        Ok(Self { projects })
+    }
+
+    pub fn to_toml(&self) -> String {
+        let paths = |paths: &BTreeSet<PathBuf>| {
+            let mut array = paths
+                .iter()
+                .map(|p| p.to_string_lossy().into_owned())
+                .collect::<toml_edit::Array>();
+            for value in array.iter_mut() {
+                value.decor_mut().set_prefix("\n  ");
+            }
+            if !array.is_empty() {
+                array.set_trailing("\n");
+                array.set_trailing_comma(true);
+            }
+            toml_edit::value(array)
+        };
+        let mut projects = ArrayOfTables::new();
+        for project in &self.projects {
+            let mut table = Table::new();
+            table.insert(
+                "path",
+                toml_edit::value(project.path.to_string_lossy().as_ref()),
+            );
+            table.insert("files", paths(&project.files));
+            table.insert("dirs", paths(&project.dirs));
+            projects.push(table);
+        }
+        let mut doc = DocumentMut::new();
+        doc.insert("project", toml_edit::Item::ArrayOfTables(projects));
+        format!(
+            "# This file is generated by ec2hx. <https://github.com/senekor/ec2hx>\n\
+             # It records the generated files, such that `ec2hx clean` can remove them.\n\n\
+             {doc}"
+        )
+    }
+
+    pub fn project(&self, path: &Path) -> Option<&Project> {
+        self.projects.iter().find(|p| p.path == path)
+    }
+
+    /// Returns the entry of a project, adding an empty one if necessary.
+    pub fn project_mut(&mut self, path: &Path) -> &mut Project {
+        match self.projects.iter().position(|p| p.path == path) {
+            Some(i) => &mut self.projects[i],
+            None => {
+                self.projects.push(Project {
+                    path: path.into(),
+                    ..Default::default()
+                });
+                self.projects.last_mut().unwrap()
+            }
+        }
+    }
+
+    pub fn remove_project(&mut self, path: &Path) -> Option<Project> {
+        let i = self.projects.iter().position(|p| p.path == path)?;
+        Some(self.projects.remove(i))
+    }
+
+    /// Whether a file or directory was also generated for another project.
+    /// Such paths must not be removed on behalf of a single project.
+    pub fn is_used_by_other(&self, project: &Path, path: &Path) -> bool {
+        self.projects
+            .iter()
+            .filter(|p| p.path != project)
+            .any(|p| p.files.contains(path) || p.dirs.contains(path))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::Manifest;
+
+    #[test]
+    fn roundtrip() {
+        let mut manifest = Manifest::default();
+        let a = manifest.project_mut(Path::new("/a"));
+        a.files.insert("/a/.helix/languages.toml".into());
+        a.files
+            .insert("/cfg/runtime/queries/ec2hx-glob-lang-x/locals.scm".into());
+        a.dirs
+            .insert("/cfg/runtime/queries/ec2hx-glob-lang-x".into());
+        manifest
+            .project_mut(Path::new("/b"))
+            .dirs
+            .insert("/b/.helix".into());
+
+        let toml = manifest.to_toml();
+        let expected = r#"# This file is generated by ec2hx. <https://github.com/senekor/ec2hx>
+# It records the generated files, such that `ec2hx clean` can remove them.
+
+[[project]]
+path = "/a"
+files = [
+  "/a/.helix/languages.toml",
+  "/cfg/runtime/queries/ec2hx-glob-lang-x/locals.scm",
+]
+dirs = [
+  "/cfg/runtime/queries/ec2hx-glob-lang-x",
+]
+
+[[project]]
+path = "/b"
+files = []
+dirs = [
+  "/b/.helix",
+]
+"#;
+        assert_eq!(toml, expected);
+        assert_eq!(Manifest::parse(&toml).unwrap(), manifest);
+    }
+
+    #[test]
+    fn shared_paths() {
+        let mut manifest = Manifest::default();
+        let shared = Path::new("/cfg/runtime/queries/ec2hx-glob-lang-x");
+        manifest
+            .project_mut(Path::new("/a"))
+            .dirs
+            .insert(shared.into());
+        assert!(!manifest.is_used_by_other(Path::new("/a"), shared));
+        manifest
+            .project_mut(Path::new("/b"))
+            .dirs
+            .insert(shared.into());
+        assert!(manifest.is_used_by_other(Path::new("/a"), shared));
+
+        manifest.remove_project(Path::new("/b"));
+        assert!(!manifest.is_used_by_other(Path::new("/a"), shared));
+    }
+}
@@ -0,0 +1,350 @@
+//! Updates previously generated files without clobbering manual changes.
+//!
+//! This is a three-way merge between the file on disk, the newly generated
+//! configuration and the configuration ec2hx generated last time (the base).
+//! Entries are compared one by one. An entry that was changed manually is
+//! kept, unless ec2hx wants to change it as well. That's reported as a
+//! conflict.
+
+use std::str::FromStr;
+
+use toml_edit::{DocumentMut, Item, Table, TableLike, Value};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Merged {
+    /// conflicts are resolved in favor of the current file
+    pub content: String,
+    /// conflicts are resolved in favor of the generated configuration
+    pub proposed: String,
+    /// the conflicting entries, e.g. "language.rust.indent"
+    pub conflicts: Vec<String>,
+}
+
+/// Without a base, ec2hx can't tell which entries it owns. Entries that only
+/// exist in the current file are kept in that case.
+pub fn merge(
+    base: Option<&str>,
+    current: &str,
+    generated: &str,
+) -> Result<Merged, toml_edit::TomlError> {
+    let base = match base {
+        Some(base) => entries(&DocumentMut::from_str(base)?),
+        None => Vec::new(),
+    };
+    let mut doc = DocumentMut::from_str(current)?;
+    let current = entries(&doc);
+    let generated = entries(&DocumentMut::from_str(generated)?);
+
+    let find = |entries: &[Entry], path: &Path| {
+        entries
+            .iter()
+            .find(|(p, _)| p == path)
+            .map(|(_, v)| canonical(v))
+    };
+
+    // generated entries first to preserve their order for new tables
+    let mut paths = generated.iter().map(|(p, _)| p).collect::<Vec<_>>();
+    for (path, _) in &base {
+        if !paths.contains(&path) {
+            paths.push(path);
+        }
+    }
+
+    let mut updates = Vec::new();
+    let mut conflicts = Vec::new();
+    for path in paths {
+        let base_value = find(&base, path);
+        let current_value = find(&current, path);
+        let generated_value = generated.iter().find(|(p, _)| p == path).map(|(_, v)| v);
+        let generated_canonical = generated_value.map(canonical);
+
+        if generated_canonical == base_value || generated_canonical == current_value {
+            // ec2hx didn't change its mind or the file is already up to date
+        } else if current_value == base_value {
+            updates.push((path, generated_value));
+        } else {
+            conflicts.push((path, generated_value));
+        }
+    }
+
+    for (path, value) in updates {
+        set(doc.as_table_mut(), path, value);
+    }
+    let content = doc.to_string();
+    for (path, value) in &conflicts {
+        set(doc.as_table_mut(), path, *value);
+    }
+    Ok(Merged {
+        content,
+        proposed: doc.to_string(),
+        conflicts: conflicts.into_iter().map(|(p, _)| display(p)).collect(),
+    })
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Segment {
+    Key(String),
+    /// an element of an array of tables, identified by its "name" key
+    Named(String, String),
+}
+
+type Path = Vec<Segment>;
+type Entry = (Path, Value);
+
+fn display(path: &Path) -> String {
+    path.iter()
+        .map(|segment| match segment {
+            Segment::Key(key) => key.clone(),
+            Segment::Named(array, name) => format!("{array}.{name}"),
+        })
+        .collect::<Vec<_>>()
+        .join(".")
+}
+
+/// Flattens a document into its values, in document order. Inline tables are
+/// treated as a single value.
+fn entries(doc: &DocumentMut) -> Vec<Entry> {
+    fn collect(table: &dyn TableLike, prefix: &Path, out: &mut Vec<Entry>) {
+        for (key, item) in table.iter() {
+            let mut path = prefix.clone();
+            match item {
+                Item::Value(value) => {
+                    path.push(Segment::Key(key.into()));
+                    out.push((path, value.clone()));
+                }
+                Item::Table(table) => {
+                    path.push(Segment::Key(key.into()));
+                    collect(table, &path, out);
+                }
+                Item::ArrayOfTables(array) => {
+                    for table in array.iter() {
+                        let Some(name) = table.get("name").and_then(|n| n.as_str()) else {
+                            continue;
+                        };
+                        let mut path = path.clone();
+                        path.push(Segment::Named(key.into(), name.into()));
+                        collect(table, &path, out);
+                    }
+                }
+                Item::None => {}
+            }
+        }
+    }
+    let mut out = Vec::new();
+    collect(doc.as_table(), &Vec::new(), &mut out);
+    // The name identifies array elements, it's not an entry on its own.
+    out.retain(|(path, _)| {
+        !matches!(path.as_slice(), [.., Segment::Named(..), Segment::Key(k)] if k == "name")
+    });
+    out
+}
+
+/// A representation of a value that ignores formatting.
+fn canonical(value: &Value) -> String {
+    match value {
+        Value::String(s) => format!("{:?}", s.value()),
+        Value::Integer(i) => i.value().to_string(),
+        Value::Float(f) => f.value().to_string(),
+        Value::Boolean(b) => b.value().to_string(),
+        Value::Datetime(d) => d.value().to_string(),
+        Value::Array(array) => {
+            let values = array.iter().map(canonical).collect::<Vec<_>>();
+            format!("[{}]", values.join(","))
+        }
+        Value::InlineTable(table) => {
+            let mut values = table
+                .iter()
+                .map(|(k, v)| format!("{k:?}={}", canonical(v)))
+                .collect::<Vec<_>>();
+            values.sort();
+            format!("{{{}}}", values.join(","))
+        }
+    }
+}
+
+/// Sets or removes a value, creating or removing the surrounding tables as
+/// needed. The formatting of an existing value is preserved.
+fn set(table: &mut dyn TableLike, path: &[Segment], value: Option<&Value>) {
+    match path {
+        [] => {}
+        [Segment::Key(key)] => match value {
+            Some(value) => {
+                let mut value = value.clone();
+                match table.get_mut(key) {
+                    // replaced in place to keep the decor of the key
+                    Some(Item::Value(old)) => {
+                        *value.decor_mut() = old.decor().clone();
+                        *old = value;
+                    }
+                    _ => {
+                        table.insert(key, Item::Value(value));
+                    }
+                }
+            }
+            None => {
+                table.remove(key);
+            }
+        },
+        [Segment::Key(key), rest @ ..] => {
+            if !table.contains_key(key) {
+                if value.is_none() {
+                    return;
+                }
+                let mut new_table = Table::new();
+                new_table.set_dotted(true);
+                table.insert(key, Item::Table(new_table));
+            }
+            let Some(inner) = table.get_mut(key).and_then(|t| t.as_table_like_mut()) else {
+                return;
+            };
+            set(inner, rest, value);
+            if inner.is_empty() {
+                table.remove(key);
+            }
+        }
+        [Segment::Named(key, name), rest @ ..] => {
+            if !table.contains_key(key) {
+                if value.is_none() {
+                    return;
+                }
+                table.insert(key, Item::ArrayOfTables(Default::default()));
+            }
+            let Some(array) = table.get_mut(key).and_then(|a| a.as_array_of_tables_mut()) else {
+                return;
+            };
+            let has_name = |t: &Table| t.get("name").and_then(|n| n.as_str()) == Some(name);
+            if !array.iter().any(has_name) {
+                if value.is_none() {
+                    return;
+                }
+                let mut new_table = Table::new();
+                new_table.insert("name", toml_edit::value(name.as_str()));
+                array.push(new_table);
+            }
+            let inner = array.iter_mut().find(|t| has_name(t)).unwrap();
+            set(inner, rest, value);
+            // An element with nothing but a name is useless.
+            array.retain(|t| !has_name(t) || t.len() > 1);
+            if array.is_empty() {
+                table.remove(key);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::merge;
+
+    const BASE: &str = r#"# generated
+editor.text-width = 80
+
+[[language]]
+name = "rust"
+indent = { unit = "  ", tab-width = 2 }
+
+[[language]]
+name = "python"
+indent = { unit = "  ", tab-width = 2 }
+"#;
+
+    #[test]
+    fn manual_changes_are_kept() {
+        let current = r#"# generated
+editor.text-width = 80
+editor.line-number = "relative"
+
+[[language]]
+name = "rust"
+indent = { unit = "    ", tab-width = 4 } # I like it this way
+language-servers = ["rust-analyzer"]
+
+[[language]]
+name = "python"
+indent = { unit = "  ", tab-width = 2 }
+
+[[language]]
+name = "mine"
+scope = "text.plain"
+"#;
+        let generated = r#"# generated
+editor.text-width = 100
+
+[[language]]
+name = "go"
+indent = { unit = "\t", tab-width = 2 }
+
+[[language]]
+name = "rust"
+indent = { unit = "  ", tab-width = 2 }
+"#;
+        let merged = merge(Some(BASE), current, generated).unwrap();
+        let expected = r#"# generated
+editor.text-width = 100
+editor.line-number = "relative"
+
+[[language]]
+name = "rust"
+indent = { unit = "    ", tab-width = 4 } # I like it this way
+language-servers = ["rust-analyzer"]
+
+[[language]]
+name = "mine"
+scope = "text.plain"
+
+[[language]]
+name = "go"
+indent = { unit = "\t", tab-width = 2 }
+"#;
+        assert_eq!(merged.content, expected);
+        assert_eq!(merged.content, merged.proposed);
+        assert!(merged.conflicts.is_empty());
+    }
+
+    #[test]
+    fn conflicts() {
+        let current = r#"# generated
+editor.text-width = 90
+
+[[language]]
+name = "rust"
+indent = { unit = "    ", tab-width = 4 }
+
+[[language]]
+name = "python"
+indent = { unit = "  ", tab-width = 2 }
+"#;
+        let generated = r#"# generated
+editor.text-width = 100
+
+[[language]]
+name = "rust"
+indent = { unit = "\t", tab-width = 4 }
+
+[[language]]
+name = "python"
+indent = { unit = "  ", tab-width = 2 }
+"#;
+        let merged = merge(Some(BASE), current, generated).unwrap();
+        assert_eq!(merged.content, current);
+        assert_eq!(
+            merged.conflicts,
+            ["editor.text-width", "language.rust.indent"]
+        );
+        assert_eq!(merged.proposed, generated);
+
+        // without a base, every difference is a conflict
+        let merged = merge(None, current, generated).unwrap();
+        assert_eq!(merged.content, current);
+        assert_eq!(
+            merged.conflicts,
+            ["editor.text-width", "language.rust.indent"]
+        );
+
+        // and entries which are not generated are kept
+        let generated = "# generated\neditor.text-width = 100\n";
+        let merged = merge(None, current, generated).unwrap();
+        assert_eq!(merged.conflicts, ["editor.text-width"]);
+        assert_eq!(merged.proposed, current.replace("90", "100"));
+    }
+}
@@ -1,76 +1,112 @@
 use std::str::FromStr;
 
-use crate::{FileType, HelixLangCfg, IndentStyle};
+use toml_edit::Table;
 
+use crate::{FileType, HelixLangCfg, Indent, IndentStyle};
+
+/// Parses a languages.toml which is known to be valid, like the bundled one.
 pub fn languages(input: &str) -> Vec<HelixLangCfg> {
-    let input = toml_edit::DocumentMut::from_str(input).unwrap();
-    input["language"]
-        .as_array_of_tables()
-        .unwrap()
-        .iter()
-        .map(|raw_toml| {
-            let lang = raw_toml;
+    try_languages(input).unwrap()
+}
 
-            let name = lang.get("name").unwrap().as_str().unwrap().to_string();
+/// Parses a languages.toml from a source that can't be trusted, e.g. a
+/// download that may have failed or been truncated. Every language must have
+/// its file types, like in the languages.toml of Helix.
+pub fn try_languages(input: &str) -> Result<Vec<HelixLangCfg>, String> {
+    let languages = parse(input)?;
+    if let Some(lang) = languages.iter().find(|lang| lang.file_types.is_none()) {
+        return Err(format!(
+            "the file-types of language {} are missing",
+            lang.name
+        ));
+    }
+    Ok(languages)
+}
 
-            let file_types = lang.get("file-types").map(|ft| {
-                ft.as_array()
-                    .unwrap()
-                    .iter()
-                    .map(|file_type| {
-                        if let Some(file_type) = file_type.as_str() {
-                            FileType::Extension(file_type.to_string())
-                        } else {
-                            FileType::Glob(
-                                file_type
-                                    .as_inline_table()
-                                    .unwrap()
-                                    .get("glob")
-                                    .unwrap()
-                                    .as_str()
-                                    .unwrap()
-                                    .to_string(),
-                            )
-                        }
-                    })
-                    .collect()
-            });
+/// Parses the languages.toml of the user. Its languages usually only override
+/// some settings of the languages of Helix, the file types may be missing.
+pub fn user_languages(input: &str) -> Result<Vec<HelixLangCfg>, String> {
+    parse(input)
+}
 
-            let indent = if let Some(indent) = lang.get("indent") {
-                let size = indent
-                    .get("tab-width")
-                    .unwrap()
-                    .as_integer()
-                    .unwrap()
-                    .try_into()
-                    .unwrap();
-                let unit = indent.get("unit").unwrap().as_str().unwrap();
-                let style = if unit.starts_with(' ') {
-                    IndentStyle::Space
-                } else {
-                    // This is exactly how Helix behaves, everything that's not a
-                    // space is a tab.
-                    IndentStyle::Tab
-                };
-                Some((size, style))
-            } else {
-                None
-            };
+fn parse(input: &str) -> Result<Vec<HelixLangCfg>, String> {
+    let input = toml_edit::DocumentMut::from_str(input).map_err(|e| e.to_string())?;
+    input
+        .get("language")
+        .and_then(|l| l.as_array_of_tables())
+        .ok_or("there are no language definitions")?
+        .iter()
+        .map(language)
+        .collect()
+}
 
-            // This is overly caution, because the configured LSP or formatter
-            // may not even be installed. In main.rs, ec2hx tries to get more
-            // precise information from `hx --health`, but this is a good
-            // default in case that fails.
-            let has_formatter =
-                lang.get("language-servers").is_some() || lang.get("formatter").is_some();
+fn language(lang: &Table) -> Result<HelixLangCfg, String> {
+    let name = lang
+        .get("name")
+        .and_then(|n| n.as_str())
+        .ok_or("a language has no name")?
+        .to_string();
+    let invalid = |key: &str| format!("the {key} of language {name} are invalid");
 
-            HelixLangCfg {
-                name,
-                indent,
-                file_types,
-                has_formatter,
-                raw_toml: lang.clone(),
-            }
+    let file_types = lang
+        .get("file-types")
+        .map(|ft| {
+            ft.as_array()
+                .ok_or_else(|| invalid("file-types"))?
+                .iter()
+                .map(|file_type| {
+                    if let Some(file_type) = file_type.as_str() {
+                        Ok(FileType::Extension(file_type.to_string()))
+                    } else {
+                        file_type
+                            .as_inline_table()
+                            .and_then(|t| t.get("glob"))
+                            .and_then(|g| g.as_str())
+                            .map(|glob| FileType::Glob(glob.to_string()))
+                            .ok_or_else(|| invalid("file-types"))
+                    }
+                })
+                .collect::<Result<Vec<_>, _>>()
         })
-        .collect()
+        .transpose()?;
+
+    let indent = if let Some(indent) = lang.get("indent") {
+        let tab_width = indent
+            .get("tab-width")
+            .and_then(|w| w.as_integer())
+            .and_then(|w| w.try_into().ok())
+            .ok_or_else(|| invalid("indent settings"))?;
+        let unit = indent
+            .get("unit")
+            .and_then(|u| u.as_str())
+            .ok_or_else(|| invalid("indent settings"))?;
+        let (style, unit_width) = if unit.starts_with(' ') {
+            (IndentStyle::Space, unit.len())
+        } else {
+            // This is exactly how Helix behaves, everything that's not a
+            // space is a tab.
+            (IndentStyle::Tab, tab_width)
+        };
+        Some(Indent {
+            style,
+            unit_width,
+            tab_width,
+        })
+    } else {
+        None
+    };
+
+    // This is overly caution, because the configured LSP or formatter
+    // may not even be installed. In main.rs, ec2hx tries to get more
+    // precise information from `hx --health`, but this is a good
+    // default in case that fails.
+    let has_formatter = lang.get("language-servers").is_some() || lang.get("formatter").is_some();
+
+    Ok(HelixLangCfg {
+        name,
+        indent,
+        file_types,
+        has_formatter,
+        raw_toml: lang.clone(),
+    })
 }
@@ -0,0 +1,179 @@
+//! A machine-readable stamp in the header of the generated files. It records
+//! what the file was generated from, such that output of an older ec2hx or
+//! for a different Helix version can be detected.
+
+use std::fmt;
+
+const NOTICE: &str = "# This file is generated by ec2hx.";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Stamp {
+    /// version of ec2hx
+    pub version: String,
+    /// commit hash of the Helix version, unknown with --portable
+    pub hx: Option<String>,
+    /// hash of the .editorconfig, see [hash]
+    pub input: String,
+    /// the CLI options which influence the output
+    pub options: String,
+}
+
+impl Stamp {
+    /// Finds the stamp in the header of a generated file.
+    pub fn parse(file: &str) -> Option<Self> {
+        let field = |key: &str| {
+            file.lines()
+                .take_while(|line| line.starts_with('#'))
+                .find_map(|line| line.strip_prefix(&format!("# ec2hx-{key}:")))
+                .map(|value| value.trim().to_string())
+        };
+        Some(Self {
+            version: field("version")?,
+            hx: field("hx").filter(|hx| hx != "unknown"),
+            input: field("input")?,
+            options: field("options").unwrap_or_default(),
+        })
+    }
+
+    /// Whether the file was generated by this version of ec2hx for this
+    /// version of Helix with the same options. If either Helix version is
+    /// unknown, it's ignored. The input isn't compared, changes to the
+    /// .editorconfig are merged with the changes of the user as usual.
+    pub fn is_current(&self, current: &Stamp) -> bool {
+        self.version == current.version
+            && (self.hx.is_none() || current.hx.is_none() || self.hx == current.hx)
+            && self.options == current.options
+    }
+}
+
+impl fmt::Display for Stamp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "# ec2hx-version: {}", self.version)?;
+        writeln!(f, "# ec2hx-hx: {}", self.hx.as_deref().unwrap_or("unknown"))?;
+        writeln!(f, "# ec2hx-input: {}", self.input)?;
+        match self.options.as_str() {
+            "" => writeln!(f, "# ec2hx-options:"),
+            options => writeln!(f, "# ec2hx-options: {options}"),
+        }
+    }
+}
+
+/// Whether a file starts with the notice of ec2hx. Such a file was generated,
+/// even if it doesn't have a stamp because it's from an older version.
+pub fn is_generated(file: &str) -> bool {
+    file.starts_with(NOTICE)
+}
+
+/// Replaces the header of a generated file, which is the first block of
+/// comment lines. Any other comments are kept.
+pub fn replace_header(file: &str, header: &str) -> String {
+    if !is_generated(file) {
+        return file.into();
+    }
+    let body = file.trim_start_matches(|c| c != '\n');
+    let mut lines = body.split_inclusive('\n').skip(1).peekable();
+    while lines.next_if(|line| line.starts_with('#')).is_some() {}
+    let rest = lines.collect::<String>();
+    format!("{}\n{}", header.trim_end(), rest)
+}
+
+/// A stable hash of the input, FNV-1a in hexadecimal. The hash of the standard
+/// library may differ between Rust versions.
+pub fn hash(input: &str) -> String {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in input.bytes() {
+        hash ^= u64::from(byte);
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    format!("{hash:016x}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stamp() -> Stamp {
+        Stamp {
+            version: "1.8.0".into(),
+            hx: Some("a1b2c3d4".into()),
+            input: hash("[*]\nindent_style = tab\n"),
+            options: "--minimal --soft-wrap".into(),
+        }
+    }
+
+    #[test]
+    fn roundtrip() {
+        let file = format!("{NOTICE}\n#\n{}\n[editor]\n", stamp());
+        assert_eq!(Stamp::parse(&file), Some(stamp()));
+
+        let portable = Stamp {
+            hx: None,
+            ..stamp()
+        };
+        let file = format!("{NOTICE}\n#\n{portable}\n[editor]\n");
+        assert_eq!(Stamp::parse(&file), Some(portable));
+
+        assert_eq!(Stamp::parse(&format!("{NOTICE}\n\n[editor]\n")), None);
+    }
+
+    #[test]
+    fn is_current() {
+        let current = stamp();
+        assert!(current.is_current(&current));
+        let old = Stamp {
+            version: "1.7.2".into(),
+            ..stamp()
+        };
+        assert!(!old.is_current(&current));
+        let other_hx = Stamp {
+            hx: Some("e5f6".into()),
+            ..stamp()
+        };
+        assert!(!other_hx.is_current(&current));
+        let portable = Stamp {
+            hx: None,
+            ..stamp()
+        };
+        assert!(portable.is_current(&current));
+        let other_options = Stamp {
+            options: "--minimal".into(),
+            ..stamp()
+        };
+        assert!(!other_options.is_current(&current));
+        let other_input = Stamp {
+            input: hash(""),
+            ..stamp()
+        };
+        assert!(other_input.is_current(&current));
+    }
+
+    #[test]
+    fn header() {
+        let file = "\
+# This file is generated by ec2hx. <https://github.com/senekor/ec2hx>
+#
+# ec2hx-version: 1.7.2
+
+# my own comment
+[editor]
+";
+        let header = "# This file is generated by ec2hx.\n#\n# ec2hx-version: 1.8.0\n";
+        let expected = "\
+# This file is generated by ec2hx.
+#
+# ec2hx-version: 1.8.0
+
+# my own comment
+[editor]
+";
+        assert_eq!(replace_header(file, header), expected);
+        // files of the user are left alone
+        assert_eq!(replace_header("[editor]\n", header), "[editor]\n");
+    }
+
+    #[test]
+    fn stable_hash() {
+        assert_eq!(hash(""), "cbf29ce484222325");
+        assert_eq!(hash("a"), "af63dc4c8601ec8c");
+    }
+}
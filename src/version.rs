@@ -0,0 +1,128 @@
+//! The version of the installed Helix, which determines the languages.toml
+//! ec2hx uses. Builds from source report a commit hash, but packaged releases
+//! often only report the release version.
+
+use std::fmt;
+
+use crate::DEFAULT_LANGUAGES;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HxVersion {
+    /// release version, e.g. "25.01.1"
+    pub release: Option<String>,
+    /// commit hash, possibly abbreviated
+    pub hash: Option<String>,
+}
+
+impl HxVersion {
+    /// Parses the output of `hx --version`, e.g. "helix 25.01.1 (7275b7f8)".
+    /// Distributions sometimes put other things than a hash in parentheses.
+    pub fn parse(output: &str) -> Option<Self> {
+        let (before, rest) = output.split_once('(').unwrap_or((output, ""));
+        let release = before
+            .split_whitespace()
+            .nth(1)
+            .filter(|v| v.starts_with(|c: char| c.is_ascii_digit()))
+            .filter(|v| v.chars().all(|c| c.is_ascii_digit() || c == '.'))
+            .map(String::from);
+        let hash = rest
+            .split(')')
+            .next()
+            .map(str::trim)
+            .filter(|h| !h.is_empty())
+            .filter(|h| {
+                h.chars()
+                    .all(|c| c.is_ascii_digit() || ('a'..='f').contains(&c))
+            })
+            .map(String::from);
+        if release.is_none() && hash.is_none() {
+            return None;
+        }
+        Some(Self { release, hash })
+    }
+
+    /// The version of the languages.toml bundled with ec2hx, from its header.
+    pub fn bundled() -> Self {
+        let version = DEFAULT_LANGUAGES
+            .lines()
+            .find_map(|line| line.strip_prefix("# Version: "))
+            .unwrap_or_default();
+        let mut parts = version.split(" - ");
+        Self {
+            release: parts.next().map(String::from),
+            hash: parts.nth(1).map(String::from),
+        }
+    }
+
+    /// The git revision to get the languages.toml from: the commit if it's
+    /// known, the release tag otherwise.
+    pub fn git_ref(&self) -> &str {
+        self.hash
+            .as_deref()
+            .or(self.release.as_deref())
+            .unwrap_or_default()
+    }
+
+    /// Whether the languages.toml bundled with ec2hx belongs to this version.
+    pub fn is_bundled(&self) -> bool {
+        let bundled = Self::bundled();
+        match (&self.hash, &bundled.hash) {
+            (Some(hash), Some(bundled)) => bundled.starts_with(hash.as_str()),
+            _ => self.release.is_some() && self.release == bundled.release,
+        }
+    }
+}
+
+impl fmt::Display for HxVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.release, &self.hash) {
+            (Some(release), Some(hash)) => write!(f, "{release} ({hash})"),
+            (Some(version), None) | (None, Some(version)) => f.write_str(version),
+            (None, None) => f.write_str("unknown"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::HxVersion;
+
+    fn version(release: Option<&str>, hash: Option<&str>) -> HxVersion {
+        HxVersion {
+            release: release.map(String::from),
+            hash: hash.map(String::from),
+        }
+    }
+
+    #[test]
+    fn parse() {
+        let parse = HxVersion::parse;
+        assert_eq!(
+            parse("helix 25.01.1 (7275b7f8)\n"),
+            Some(version(Some("25.01.1"), Some("7275b7f8")))
+        );
+        assert_eq!(
+            parse("helix 25.01.1\n"),
+            Some(version(Some("25.01.1"), None))
+        );
+        assert_eq!(
+            parse("helix 24.07 (Fedora 24.07-2.fc41)\n"),
+            Some(version(Some("24.07"), None))
+        );
+        assert_eq!(parse("helix\n"), None);
+        assert_eq!(parse(""), None);
+    }
+
+    #[test]
+    fn bundled() {
+        let bundled = HxVersion::bundled();
+        assert_eq!(bundled.release.as_deref(), Some("25.01"));
+        assert!(bundled.is_bundled());
+        assert_eq!(bundled.git_ref(), bundled.hash.as_deref().unwrap());
+
+        assert!(version(Some("25.01"), None).is_bundled());
+        assert!(!version(Some("25.01.1"), None).is_bundled());
+        assert!(version(Some("25.01.1"), Some("c9cc1472")).is_bundled());
+        assert!(!version(Some("25.01"), Some("7275b7f8")).is_bundled());
+    }
+}
@@ -0,0 +1,79 @@
+//! Runs the ec2hx binary in a temporary project with its own data, config and
+//! cache directories, such that the manifest of the user is not touched.
+#![cfg(unix)]
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("ec2hx-test-{}-{name}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(dir.join("project").join(".git")).unwrap();
+    dir
+}
+
+fn ec2hx(dir: &Path, args: &[&str]) {
+    let status = Command::new(env!("CARGO_BIN_EXE_ec2hx"))
+        .args(args)
+        .current_dir(dir.join("project"))
+        .env("XDG_DATA_HOME", dir.join("data"))
+        .env("XDG_CONFIG_HOME", dir.join("config"))
+        .env("XDG_CACHE_HOME", dir.join("cache"))
+        .stdout(Stdio::null())
+        .status()
+        .unwrap();
+    assert!(status.success());
+}
+
+#[test]
+fn files_of_the_user_are_kept() {
+    let dir = temp_dir("user-files");
+    let project = dir.join("project");
+    let helix = project.join(".helix");
+    fs::create_dir(&helix).unwrap();
+    fs::write(helix.join("config.toml"), "theme = \"onedark\"\n").unwrap();
+
+    fs::write(
+        project.join(".editorconfig"),
+        "[*]\nend_of_line = lf\n\n[*.rs]\nindent_size = 2\n",
+    )
+    .unwrap();
+    ec2hx(&dir, &["--portable"]);
+    fs::write(
+        project.join(".editorconfig"),
+        "[*]\nend_of_line = crlf\n\n[*.rs]\nindent_size = 2\n",
+    )
+    .unwrap();
+    ec2hx(&dir, &["--portable"]);
+
+    let config = fs::read_to_string(helix.join("config.toml")).unwrap();
+    assert!(config.contains("theme = \"onedark\""));
+    assert!(config.contains("editor.default-line-ending = \"crlf\""));
+    let manifest = fs::read_to_string(dir.join("data/ec2hx/manifest.toml")).unwrap();
+    assert!(manifest.contains("languages.toml"));
+    // neither the file of the user nor its backups
+    assert!(!manifest.contains("/.helix/config.toml"));
+
+    ec2hx(&dir, &["clean"]);
+    assert!(!helix.join("languages.toml").exists());
+    assert!(!helix.join(".ec2hx").exists());
+    assert!(!dir.join("data/ec2hx/manifest.toml").exists());
+    // the merged entries stay, the file isn't ec2hx's to remove
+    assert_eq!(
+        fs::read_to_string(helix.join("config.toml")).unwrap(),
+        config
+    );
+    let backups = fs::read_dir(&helix)
+        .unwrap()
+        .flatten()
+        .map(|entry| entry.file_name().to_string_lossy().into_owned())
+        .filter(|name| name.ends_with(".bak"))
+        .collect::<Vec<_>>();
+    assert_eq!(backups.len(), 2);
+    assert!(backups.iter().all(|name| name.starts_with("config.toml.")));
+
+    fs::remove_dir_all(dir).unwrap();
+}